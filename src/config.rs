@@ -0,0 +1,188 @@
+use rusoto_core::Region;
+
+use crate::{DecodeMode, DuplicatePolicy, SessionLifetime};
+use crate::presign::S3Config;
+use crate::retry::RetryPolicy;
+
+pub const DEFAULT_TABLE_NAME: &str = "insignia-docs";
+pub const DEFAULT_VERTEX_B_INDEX: &str = "index-vertex_b_edges";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BillingMode {
+    Provisioned {
+        read_capacity_units: i64,
+        write_capacity_units: i64
+    },
+    PayPerRequest
+}
+
+/// Settings for a DynamoDB backed `GraphDb`. Start from `GraphDbConfig::new()` and chain the setters.
+#[derive(Clone, Debug)]
+pub struct GraphDbConfig {
+    pub table_name: String,
+    pub vertex_b_index: String,
+    pub billing_mode: BillingMode,
+    pub region: Region,
+    pub decode_mode: DecodeMode,
+    pub retry_policy: RetryPolicy,
+    /// Where documents are stored. Defaults to the DynamoDB region when not set.
+    pub s3: Option<S3Config>,
+    /// Check uploaded objects in S3 before marking documents as uploaded.
+    pub verify_uploads: bool,
+    pub duplicate_policy: DuplicatePolicy,
+    pub session_lifetime: SessionLifetime
+}
+
+impl Default for GraphDbConfig {
+    fn default() -> GraphDbConfig {
+        GraphDbConfig {
+            table_name: String::from(DEFAULT_TABLE_NAME),
+            vertex_b_index: String::from(DEFAULT_VERTEX_B_INDEX),
+            billing_mode: BillingMode::Provisioned {
+                read_capacity_units: 1,
+                write_capacity_units: 1
+            },
+            region: Region::default(),
+            decode_mode: DecodeMode::Strict,
+            retry_policy: RetryPolicy::default(),
+            s3: None,
+            verify_uploads: false,
+            duplicate_policy: DuplicatePolicy::Allow,
+            session_lifetime: SessionLifetime::default()
+        }
+    }
+}
+
+impl GraphDbConfig {
+    pub fn new() -> GraphDbConfig {
+        GraphDbConfig::default()
+    }
+
+    pub fn table_name(self, table_name: &str) -> GraphDbConfig {
+        GraphDbConfig {
+            table_name: String::from(table_name),
+            ..self
+        }
+    }
+
+    pub fn vertex_b_index(self, vertex_b_index: &str) -> GraphDbConfig {
+        GraphDbConfig {
+            vertex_b_index: String::from(vertex_b_index),
+            ..self
+        }
+    }
+
+    pub fn billing_mode(self, billing_mode: BillingMode) -> GraphDbConfig {
+        GraphDbConfig {
+            billing_mode,
+            ..self
+        }
+    }
+
+    pub fn region(self, region: Region) -> GraphDbConfig {
+        GraphDbConfig {
+            region,
+            ..self
+        }
+    }
+
+    /// Use a custom endpoint, e.g. DynamoDB Local on `http://localhost:8000`.
+    pub fn endpoint(self, region_name: &str, endpoint: &str) -> GraphDbConfig {
+        self.region(Region::Custom {
+            name: region_name.to_owned(),
+            endpoint: endpoint.to_owned(),
+        })
+    }
+
+    pub fn decode_mode(self, decode_mode: DecodeMode) -> GraphDbConfig {
+        GraphDbConfig {
+            decode_mode,
+            ..self
+        }
+    }
+
+    pub fn s3(self, s3: S3Config) -> GraphDbConfig {
+        GraphDbConfig {
+            s3: Some(s3),
+            ..self
+        }
+    }
+
+    /// The S3 settings to use, falling back to the DynamoDB region.
+    pub fn s3_config(&self) -> S3Config {
+        self.s3.clone().unwrap_or_else(|| S3Config::new(self.region.clone()))
+    }
+
+    pub fn verify_uploads(self, verify_uploads: bool) -> GraphDbConfig {
+        GraphDbConfig {
+            verify_uploads,
+            ..self
+        }
+    }
+
+    pub fn duplicate_policy(self, duplicate_policy: DuplicatePolicy) -> GraphDbConfig {
+        GraphDbConfig {
+            duplicate_policy,
+            ..self
+        }
+    }
+
+    pub fn session_lifetime(self, session_lifetime: SessionLifetime) -> GraphDbConfig {
+        GraphDbConfig {
+            session_lifetime,
+            ..self
+        }
+    }
+
+    /// Retry policy shared by every DynamoDB call and by the retries of unprocessed batch items.
+    pub fn retry_policy(self, retry_policy: RetryPolicy) -> GraphDbConfig {
+        GraphDbConfig {
+            retry_policy,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::GraphDb;
+
+    #[test]
+    fn from_config_uses_table_and_index_names() {
+        let config = GraphDbConfig::new()
+            .table_name("insignia-docs-staging")
+            .vertex_b_index("staging-vertex_b_edges")
+            .billing_mode(BillingMode::PayPerRequest)
+            .endpoint("eu-north-1", "http://localhost:8000")
+            .decode_mode(DecodeMode::Lenient)
+            .retry_policy(RetryPolicy::new().max_attempts(3));
+        let db = GraphDb::from_config(config).unwrap();
+
+        assert_eq!(db.store.table_name, "insignia-docs-staging");
+        assert_eq!(db.store.vertex_b_index, "staging-vertex_b_edges");
+        assert_eq!(db.store.billing_mode, BillingMode::PayPerRequest);
+        assert_eq!(db.decode_mode, DecodeMode::Lenient);
+        assert_eq!(db.retry_policy.max_attempts, 3);
+        assert_eq!(db.store.retry_policy.max_attempts, 3);
+    }
+
+    #[test]
+    fn defaults_match_existing_table() {
+        let config = GraphDbConfig::new();
+        assert_eq!(config.table_name, "insignia-docs");
+        assert_eq!(config.vertex_b_index, "index-vertex_b_edges");
+        assert_eq!(config.decode_mode, DecodeMode::Strict);
+    }
+
+    #[test]
+    fn s3_defaults_to_the_dynamodb_region() {
+        let config = GraphDbConfig::new().region(Region::EuWest1);
+        assert_eq!(config.s3_config().region, Region::EuWest1);
+
+        let config = config.s3(S3Config::endpoint("local", "http://localhost:9000"));
+        assert_eq!(config.s3_config().region.name(), "local");
+        assert_eq!(GraphDb::from_config(config).unwrap().s3.region.name(), "local");
+    }
+}
@@ -1,5 +1,9 @@
 use std::str::FromStr;
 
+use chrono::{DateTime, Duration, Utc};
+
+use crate::error::DatastoreError;
+
 pub enum EdgeType {
     SessionSelf,
     SessionUser,
@@ -11,6 +15,11 @@ pub enum EdgeType {
     UserEmail,
     UserPhone,
     //UserSession,
+
+    OrganisationSelf,
+    OrganisationNo,
+    OrganisationMember,
+    OrgNoSelf,
     
     DocumentSelf,
     DocumentOwner,
@@ -18,7 +27,8 @@ pub enum EdgeType {
     DocumentS3,
     DocumentChecksum,
     DocumentSignRequest,
-    DocumentSignature
+    DocumentSignature,
+    DocumentDuplicate
 }
 
 impl std::fmt::Display for EdgeType {
@@ -35,6 +45,11 @@ impl std::fmt::Display for EdgeType {
             EdgeType::UserPhone => write!(f, "usr_phone"),
             //EdgeType::UserSession => write!(f, "session"),
 
+            EdgeType::OrganisationSelf => write!(f, "org_self"),
+            EdgeType::OrganisationNo => write!(f, "org_no"),
+            EdgeType::OrganisationMember => write!(f, "org_member"),
+            EdgeType::OrgNoSelf => write!(f, "orgno_self"),
+
             EdgeType::DocumentSelf => write!(f, "doc_self"),
             EdgeType::DocumentOwner => write!(f, "doc_acl_owner"),
             EdgeType::DocumentReader => write!(f, "doc_acl_reader"),
@@ -42,13 +57,14 @@ impl std::fmt::Display for EdgeType {
             EdgeType::DocumentChecksum => write!(f, "doc_checksum"),
             EdgeType::DocumentSignRequest => write!(f, "doc_signreq"),
             EdgeType::DocumentSignature => write!(f, "doc_signature"),
+            EdgeType::DocumentDuplicate => write!(f, "doc_duplicate"),
         }
         
     }
 }
 
 impl FromStr for EdgeType {
-    type Err = std::io::Error;
+    type Err = DatastoreError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let splitted: Vec<&str> = s.trim()
@@ -64,6 +80,10 @@ impl FromStr for EdgeType {
            "usr_personal_number" => Ok(EdgeType::UserPersonalNumber),
            "usr_email" => Ok(EdgeType::UserEmail),
            "usr_phone" => Ok(EdgeType::UserPhone),
+           "org_self" => Ok(EdgeType::OrganisationSelf),
+           "org_no" => Ok(EdgeType::OrganisationNo),
+           "org_member" => Ok(EdgeType::OrganisationMember),
+           "orgno_self" => Ok(EdgeType::OrgNoSelf),
            "doc_self" => Ok(EdgeType::DocumentSelf),
            "doc_acl_owner" => Ok(EdgeType::DocumentOwner),
            "doc_acl_reader" => Ok(EdgeType::DocumentReader),
@@ -71,7 +91,8 @@ impl FromStr for EdgeType {
            "doc_checksum" => Ok(EdgeType::DocumentChecksum),
            "doc_signreq" => Ok(EdgeType::DocumentSignRequest),
            "doc_signature" => Ok(EdgeType::DocumentSignature),
-           _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid edge type"))
+           "doc_duplicate" => Ok(EdgeType::DocumentDuplicate),
+           _ => Err(DatastoreError::InvalidEdgeType(String::from(s)))
         }
     }
 }
@@ -88,7 +109,9 @@ pub enum Vertex {
     ChecksumSha256(String),
     PersonalNumber(String),
     Email(String),
-    Phone(String)
+    Phone(String),
+    Organisation(String),
+    OrgNo(String)
 }
 
 impl std::fmt::Display for Vertex {
@@ -103,21 +126,23 @@ impl std::fmt::Display for Vertex {
             Vertex::ChecksumSha256(sha) => write!(f, "SHA256-{}", sha),
             Vertex::PersonalNumber(personal_no) => write!(f, "PersonalNumber-{}", personal_no),
             Vertex::Email(email) => write!(f, "Email-{}", email),
-            Vertex::Phone(phone) => write!(f, "Phone-{}", phone)
+            Vertex::Phone(phone) => write!(f, "Phone-{}", phone),
+            Vertex::Organisation(id) => write!(f, "Organisation-{}", id),
+            Vertex::OrgNo(org_no) => write!(f, "OrgNo-{}", org_no)
         }
         
     }
 }
 
 impl FromStr for Vertex {
-    type Err = std::io::Error;
+    type Err = DatastoreError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let splitted: Vec<&str> = s.trim()
                                  .split('-')
                                  .collect();
         if splitted.len()<2 {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid vertex format"));
+            return Err(DatastoreError::InvalidVertex(String::from(s)));
         }
         match splitted[0] {
            "User" => Ok(Vertex::User(splitted[1..].join("-"))),
@@ -129,7 +154,10 @@ impl FromStr for Vertex {
            "Email" => Ok(Vertex::Email(splitted[1..].join("-"))),
            "Phone" => Ok(Vertex::Phone(splitted[1..].join("-"))),
            "SessionLogin" => Ok(Vertex::SessionLogin(splitted[1..].join("-"))),
-           _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid vertex type"))
+           "SessionLogout" => Ok(Vertex::SessionLogout(splitted[1..].join("-"))),
+           "Organisation" => Ok(Vertex::Organisation(splitted[1..].join("-"))),
+           "OrgNo" => Ok(Vertex::OrgNo(splitted[1..].join("-"))),
+           _ => Err(DatastoreError::InvalidVertex(String::from(s)))
         }
     }
 }
@@ -140,7 +168,13 @@ pub struct Edge {
     pub vertex_a: String,
     pub vertex_b: String,
     pub edge: String,
-    pub data: Option<VertexData>
+    pub data: Option<VertexData>,
+    /// Bumped on every versioned update, see `GraphDb::update_edge`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    /// Epoch seconds after which DynamoDB may delete the edge, see `TTL_ATTRIBUTE`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>
 }
 
 /*
@@ -161,6 +195,11 @@ pub enum VertexData {
     String(String),
     UserData(UserData),
     SessionData(SessionData),
+    OrganisationData(OrganisationData),
+    MembershipData(MembershipData),
+    SignRequestData(SignRequestData),
+    SignatureData(SignatureData),
+    DocumentData(DocumentData),
     None
 }
 
@@ -171,6 +210,11 @@ impl std::fmt::Display for VertexData {
             VertexData::String(data) => write!(f, "Session-{}", data),
             VertexData::UserData(data) => write!(f, "Document-{}", data),
             VertexData::SessionData(data) => write!(f, "S3-{}", data),
+            VertexData::OrganisationData(data) => write!(f, "{}", data),
+            VertexData::MembershipData(data) => write!(f, "{}", data),
+            VertexData::SignRequestData(data) => write!(f, "{}", data),
+            VertexData::SignatureData(data) => write!(f, "{}", data),
+            VertexData::DocumentData(data) => write!(f, "{}", data),
             VertexData::None => write!(f, "None")
         }
         
@@ -225,7 +269,48 @@ pub struct SessionData {
     pub session_login_id: Option<String>,
     pub login: Option<String>,
     pub logout: Option<String>,
-    pub auth_data: Option<String>
+    /// How a login edge was authenticated. Reads free text written before auth data was structured as a description.
+    #[serde(default, deserialize_with = "deserialize_auth_data")]
+    pub auth_data: Option<AuthData>,
+    /// End of the absolute lifetime of the session.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    /// Seconds the session may go unused before it expires.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_active: Option<String>,
+    /// The user a login edge logged in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>
+}
+
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time).ok().map(|time| time.with_timezone(&Utc))
+}
+
+impl SessionData {
+    /// When the session expires: at the end of its lifetime or after being idle too long, whichever comes first.
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        let absolute = self.lifetime_ends_at();
+        let idle = match (self.idle_timeout, self.last_active.as_ref().or(self.created.as_ref())) {
+            (Some(idle_timeout), Some(last_active)) => parse_time(last_active).map(|time| time + Duration::seconds(idle_timeout as i64)),
+            _ => None
+        };
+        match (absolute, idle) {
+            (Some(absolute), Some(idle)) => Some(absolute.min(idle)),
+            (absolute, idle) => absolute.or(idle)
+        }
+    }
+
+    /// The end of the session's absolute lifetime, however much it is used.
+    pub fn lifetime_ends_at(&self) -> Option<DateTime<Utc>> {
+        self.expires.as_deref().and_then(parse_time)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.expires_at(), Some(expires_at) if expires_at <= now)
+    }
 }
 
 impl std::fmt::Display for SessionData {
@@ -248,24 +333,262 @@ impl std::fmt::Display for SessionData {
             None => write!(f, ", logout: None")?
         };
         match &self.auth_data {
-            Some(s) => write!(f, ", auth_data: Some(\"{}\")", s)?,
+            Some(s) => write!(f, ", auth_data: Some({})", s)?,
             None => write!(f, ", auth_data: None")?
         };
+        match &self.expires {
+            Some(s) => write!(f, ", expires: Some(\"{}\")", s)?,
+            None => write!(f, ", expires: None")?
+        };
+        match &self.idle_timeout {
+            Some(s) => write!(f, ", idle_timeout: Some({})", s)?,
+            None => write!(f, ", idle_timeout: None")?
+        };
+        match &self.last_active {
+            Some(s) => write!(f, ", last_active: Some(\"{}\")", s)?,
+            None => write!(f, ", last_active: None")?
+        };
+        match &self.user_id {
+            Some(s) => write!(f, ", user_id: Some(\"{}\")", s)?,
+            None => write!(f, ", user_id: None")?
+        };
+        write!(f, "}}")
+    }
+}
+
+/// Stored as its name, e.g. `bankid`, so audits can filter on it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(into = "String", try_from = "String")]
+pub enum AuthMethod {
+    BankId,
+    Freja,
+    Password,
+    Sso
+}
+
+impl std::fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            AuthMethod::BankId => write!(f, "bankid"),
+            AuthMethod::Freja => write!(f, "freja"),
+            AuthMethod::Password => write!(f, "password"),
+            AuthMethod::Sso => write!(f, "sso")
+        }
+    }
+}
+
+impl FromStr for AuthMethod {
+    type Err = DatastoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bankid" => Ok(AuthMethod::BankId),
+            "freja" => Ok(AuthMethod::Freja),
+            "password" => Ok(AuthMethod::Password),
+            "sso" => Ok(AuthMethod::Sso),
+            _ => Err(DatastoreError::Serialization(format!("Unknown auth method {}", s)))
+        }
+    }
+}
+
+impl From<AuthMethod> for String {
+    fn from(method: AuthMethod) -> String {
+        method.to_string()
+    }
+}
+
+impl std::convert::TryFrom<String> for AuthMethod {
+    type Error = DatastoreError;
+
+    fn try_from(method: String) -> Result<Self, Self::Error> {
+        method.parse()
+    }
+}
+
+/// How a user authenticated a login, kept on the login edge for audits.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct AuthData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<AuthMethod>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Level of assurance reported by the identity provider, e.g. `loa3`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assurance_level: Option<String>,
+    /// Transaction id at the identity provider, e.g. the BankID order reference.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idp_transaction_id: Option<String>,
+    /// Free text, the only content of auth data stored as a string.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>
+}
+
+impl AuthData {
+    pub fn new(method: AuthMethod) -> AuthData {
+        AuthData {
+            method: Some(method),
+            ..AuthData::default()
+        }
+    }
+
+    pub fn ip(self, ip: &str) -> AuthData {
+        AuthData {
+            ip: Some(String::from(ip)),
+            ..self
+        }
+    }
+
+    pub fn user_agent(self, user_agent: &str) -> AuthData {
+        AuthData {
+            user_agent: Some(String::from(user_agent)),
+            ..self
+        }
+    }
+
+    pub fn assurance_level(self, assurance_level: &str) -> AuthData {
+        AuthData {
+            assurance_level: Some(String::from(assurance_level)),
+            ..self
+        }
+    }
+
+    pub fn idp_transaction_id(self, idp_transaction_id: &str) -> AuthData {
+        AuthData {
+            idp_transaction_id: Some(String::from(idp_transaction_id)),
+            ..self
+        }
+    }
+
+    pub fn description(self, description: &str) -> AuthData {
+        AuthData {
+            description: Some(String::from(description)),
+            ..self
+        }
+    }
+}
+
+/// Free text auth data, as `session_auth` used to take it.
+impl From<&str> for AuthData {
+    fn from(description: &str) -> AuthData {
+        AuthData::default().description(description)
+    }
+}
+
+impl std::fmt::Display for AuthData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "AuthData{{")?;
+        match &self.method {
+            Some(s) => write!(f, "method: Some(\"{}\")", s)?,
+            None => write!(f, "method: None")?
+        };
+        match &self.ip {
+            Some(s) => write!(f, ", ip: Some(\"{}\")", s)?,
+            None => write!(f, ", ip: None")?
+        };
+        match &self.user_agent {
+            Some(s) => write!(f, ", user_agent: Some(\"{}\")", s)?,
+            None => write!(f, ", user_agent: None")?
+        };
+        match &self.assurance_level {
+            Some(s) => write!(f, ", assurance_level: Some(\"{}\")", s)?,
+            None => write!(f, ", assurance_level: None")?
+        };
+        match &self.idp_transaction_id {
+            Some(s) => write!(f, ", idp_transaction_id: Some(\"{}\")", s)?,
+            None => write!(f, ", idp_transaction_id: None")?
+        };
+        match &self.description {
+            Some(s) => write!(f, ", description: Some(\"{}\")", s)?,
+            None => write!(f, ", description: None")?
+        };
         write!(f, "}}")
     }
 }
 
+/// Auth data as stored: a map, or a string for logins stored before auth data was structured.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredAuthData {
+    Text(String),
+    Structured(AuthData)
+}
+
+fn deserialize_auth_data<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<AuthData>, D::Error> {
+    use serde::Deserialize;
+    Ok(Option::<StoredAuthData>::deserialize(deserializer)?.map(|stored| match stored {
+        StoredAuthData::Text(description) => AuthData::from(description.as_str()),
+        StoredAuthData::Structured(auth_data) => auth_data
+    }))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OrganisationData {
+    pub org_no: String,
+    pub name: String
+}
+
+impl std::fmt::Display for OrganisationData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "OrganisationData{{org_no: \"{}\", name: \"{}\"}}", self.org_no, self.name)
+    }
+}
+
+/// Data on an `org_member` edge from an organisation to a user.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MembershipData {
+    pub role: String
+}
+
+impl std::fmt::Display for MembershipData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "MembershipData{{role: \"{}\"}}", self.role)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Organisation {
     pub id: String,
     pub org_no: String,
     pub name: String
 }
 
+impl std::fmt::Display for Organisation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Organisation{{id: \"{}\", org_no: \"{}\", name: \"{}\"}}", self.id, self.org_no, self.name)
+    }
+}
+
+/// A user's role in an organisation.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Membership {
+    pub organisation: Organisation,
+    pub role: String
+}
+
+/// A user's role in an organisation, seen from the organisation.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Member {
+    pub user_id: String,
+    pub role: String
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum LegalEntity {
     Org(Organisation),
     User(User)
 }
 
+impl std::fmt::Display for LegalEntity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            LegalEntity::Org(org) => write!(f, "{}", org),
+            LegalEntity::User(user) => write!(f, "{}", user)
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Session {
     pub session_id: String,
@@ -273,18 +596,25 @@ pub struct Session {
     pub created: Option<String>,
     pub login: Option<String>,
     pub logout: Option<String>,
-    pub auth_data: Option<String>,
-    pub user: Option<User>
+    pub auth_data: Option<AuthData>,
+    pub user: Option<User>,
+    /// When the session expires, if it has a lifetime or an idle timeout.
+    pub expires_at: Option<String>
 }
 
 impl Session {
+    /// Data for the login and logout edges of the session. Lifetimes are kept on the `session_self` edge only.
     pub fn session_data(&self) -> SessionData {
         SessionData{
             created: self.created.clone(), 
             session_login_id: self.session_login_id.clone(),
             login: self.login.clone(),
             logout: self.logout.clone(),
-            auth_data: self.auth_data.clone()
+            auth_data: self.auth_data.clone(),
+            expires: None,
+            idle_timeout: None,
+            last_active: None,
+            user_id: self.user.as_ref().map(|user| user.user_id.clone())
         }
     }
 }
@@ -309,13 +639,78 @@ impl std::fmt::Display for Session {
             None => write!(f, ", logout: None")?
         };
         match &self.auth_data {
-            Some(s) => write!(f, ", auth_data: Some(\"{}\")", s)?,
+            Some(s) => write!(f, ", auth_data: Some({})", s)?,
             None => write!(f, ", auth_data: None")?
         };
         match &self.user {
             Some(s) => write!(f, ", user: Some(\"{}\")", s)?,
             None => write!(f, ", user: None")?
         };
+        match &self.expires_at {
+            Some(s) => write!(f, ", expires_at: Some(\"{}\")", s)?,
+            None => write!(f, ", expires_at: None")?
+        };
+        write!(f, "}}")
+    }
+}
+
+/// One login of a user, see `GraphDb::get_user_logins`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Login {
+    pub session_id: String,
+    pub session_login_id: String,
+    pub user_id: String,
+    pub login: Option<String>,
+    /// None while the login is active.
+    pub logout: Option<String>,
+    pub auth_data: Option<AuthData>
+}
+
+impl std::fmt::Display for Login {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Login{{session_id: \"{}\", session_login_id: \"{}\", user_id: \"{}\"", self.session_id, self.session_login_id, self.user_id)?;
+        match &self.login {
+            Some(s) => write!(f, ", login: Some(\"{}\")", s)?,
+            None => write!(f, ", login: None")?
+        };
+        match &self.logout {
+            Some(s) => write!(f, ", logout: Some(\"{}\")", s)?,
+            None => write!(f, ", logout: None")?
+        };
+        match &self.auth_data {
+            Some(s) => write!(f, ", auth_data: Some({})", s)?,
+            None => write!(f, ", auth_data: None")?
+        };
+        write!(f, "}}")
+    }
+}
+
+/// A session with an active login, see `GraphDb::authenticate_session`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuthenticatedSession {
+    pub session_id: String,
+    pub session_login_id: String,
+    pub user: User,
+    pub login: Option<String>,
+    pub auth_data: Option<AuthData>,
+    pub expires_at: Option<String>
+}
+
+impl std::fmt::Display for AuthenticatedSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "AuthenticatedSession{{session_id: \"{}\", session_login_id: \"{}\", user: \"{}\"", self.session_id, self.session_login_id, self.user)?;
+        match &self.login {
+            Some(s) => write!(f, ", login: Some(\"{}\")", s)?,
+            None => write!(f, ", login: None")?
+        };
+        match &self.auth_data {
+            Some(s) => write!(f, ", auth_data: Some({})", s)?,
+            None => write!(f, ", auth_data: None")?
+        };
+        match &self.expires_at {
+            Some(s) => write!(f, ", expires_at: Some(\"{}\")", s)?,
+            None => write!(f, ", expires_at: None")?
+        };
         write!(f, "}}")
     }
 }
@@ -362,11 +757,279 @@ impl std::fmt::Display for User {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SignRequestStatus {
+    Pending,
+    Signed,
+    Declined,
+    Cancelled
+}
+
+impl std::fmt::Display for SignRequestStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            SignRequestStatus::Pending => write!(f, "Pending"),
+            SignRequestStatus::Signed => write!(f, "Signed"),
+            SignRequestStatus::Declined => write!(f, "Declined"),
+            SignRequestStatus::Cancelled => write!(f, "Cancelled")
+        }
+    }
+}
+
+/// Data on a `doc_signreq` edge from a document to the user or organisation asked to sign it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignRequestData {
+    pub status: SignRequestStatus,
+    pub requested: String,
+    pub requested_by: String,
+    pub due: Option<String>,
+    pub message: Option<String>,
+    /// When the request was signed, declined or cancelled.
+    pub resolved: Option<String>,
+    /// The user who signed, declined or cancelled the request.
+    pub resolved_by: Option<String>
+}
+
+impl std::fmt::Display for SignRequestData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SignRequestData{{status: {}, requested: \"{}\", requested_by: \"{}\"", self.status, self.requested, self.requested_by)?;
+        match &self.due {
+            Some(s) => write!(f, ", due: Some(\"{}\")", s)?,
+            None => write!(f, ", due: None")?
+        };
+        match &self.resolved {
+            Some(s) => write!(f, ", resolved: Some(\"{}\")", s)?,
+            None => write!(f, ", resolved: None")?
+        };
+        write!(f, "}}")
+    }
+}
+
+/// Data on a `doc_signature` edge: what was signed, by whom and when.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignatureData {
+    /// The `SHA256-` checksum vertex of the document at signing time.
+    pub checksum: String,
+    /// The signature as returned by the signing service, e.g. a base64 encoded BankID or PKCS#7 blob.
+    pub payload: String,
+    pub signed: String,
+    pub signed_by: String
+}
+
+impl std::fmt::Display for SignatureData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SignatureData{{checksum: \"{}\", signed: \"{}\", signed_by: \"{}\"}}", self.checksum, self.signed, self.signed_by)
+    }
+}
+
+/// One signature of a document, checked against the current checksum of the document.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignatureCheck {
+    pub signer_id: String,
+    /// None for signatures recorded before signatures carried their checksum.
+    pub signature: Option<SignatureData>,
+    pub matches_checksum: bool
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignatureVerification {
+    pub doc_id: String,
+    /// The current `SHA256-` checksum vertex of the document.
+    pub checksum: Option<String>,
+    pub signatures: Vec<SignatureCheck>
+}
+
+impl SignatureVerification {
+    /// True if any signature was made over other content than the document has now.
+    pub fn checksum_changed(&self) -> bool {
+        self.signatures.iter().any(|check| !check.matches_checksum)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignRequest {
+    pub doc_id: String,
+    pub signer_id: String,
+    pub status: SignRequestStatus,
+    pub requested: String,
+    pub requested_by: String,
+    pub due: Option<String>,
+    pub message: Option<String>,
+    pub resolved: Option<String>,
+    pub resolved_by: Option<String>
+}
+
+impl SignRequest {
+    /// Build a request from a `doc_signreq` edge. Edges stored without data count as pending requests.
+    pub fn from_edge(edge: &Edge) -> SignRequest {
+        let data = match &edge.data {
+            Some(VertexData::SignRequestData(data)) => data.clone(),
+            _ => SignRequestData {
+                status: SignRequestStatus::Pending,
+                requested: String::new(),
+                requested_by: String::new(),
+                due: None,
+                message: None,
+                resolved: None,
+                resolved_by: None
+            }
+        };
+        SignRequest {
+            doc_id: edge.vertex_a.clone(),
+            signer_id: edge.vertex_b.clone(),
+            status: data.status,
+            requested: data.requested,
+            requested_by: data.requested_by,
+            due: data.due,
+            message: data.message,
+            resolved: data.resolved,
+            resolved_by: data.resolved_by
+        }
+    }
+
+    pub fn sign_request_data(&self) -> SignRequestData {
+        SignRequestData {
+            status: self.status,
+            requested: self.requested.clone(),
+            requested_by: self.requested_by.clone(),
+            due: self.due.clone(),
+            message: self.message.clone(),
+            resolved: self.resolved.clone(),
+            resolved_by: self.resolved_by.clone()
+        }
+    }
+}
+
+/// Where a document is in its life. Documents move forward through
+/// PendingUpload, Uploaded, AwaitingSignatures and Signed, and can be archived once uploaded.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DocumentStatus {
+    PendingUpload,
+    Uploaded,
+    AwaitingSignatures,
+    Signed,
+    Archived
+}
+
+impl DocumentStatus {
+    pub fn can_become(&self, next: DocumentStatus) -> bool {
+        use DocumentStatus::*;
+        matches!((self, next),
+            (PendingUpload, Uploaded) |
+            (Uploaded, AwaitingSignatures) |
+            // every request was cancelled
+            (AwaitingSignatures, Uploaded) |
+            (AwaitingSignatures, Signed) |
+            // another signer was asked after the last signature
+            (Signed, AwaitingSignatures) |
+            (Uploaded, Archived) |
+            (AwaitingSignatures, Archived) |
+            (Signed, Archived))
+    }
+}
+
+impl std::fmt::Display for DocumentStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            DocumentStatus::PendingUpload => write!(f, "PendingUpload"),
+            DocumentStatus::Uploaded => write!(f, "Uploaded"),
+            DocumentStatus::AwaitingSignatures => write!(f, "AwaitingSignatures"),
+            DocumentStatus::Signed => write!(f, "Signed"),
+            DocumentStatus::Archived => write!(f, "Archived")
+        }
+    }
+}
+
+/// Data on the `doc_self` edge of a document.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DocumentData {
+    pub title: Option<String>,
+    pub content_type: Option<String>,
+    pub size: Option<u64>,
+    pub created: String,
+    pub modified: String,
+    pub status: DocumentStatus
+}
+
+impl std::fmt::Display for DocumentData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DocumentData{{status: {}", self.status)?;
+        match &self.title {
+            Some(s) => write!(f, ", title: Some(\"{}\")", s)?,
+            None => write!(f, ", title: None")?
+        };
+        match &self.content_type {
+            Some(s) => write!(f, ", content_type: Some(\"{}\")", s)?,
+            None => write!(f, ", content_type: None")?
+        };
+        match &self.size {
+            Some(size) => write!(f, ", size: Some({})", size)?,
+            None => write!(f, ", size: None")?
+        };
+        write!(f, ", created: \"{}\", modified: \"{}\"}}", self.created, self.modified)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum AclRole {
+    Owner,
+    Reader
+}
+
+impl AclRole {
+    pub fn edge_type(&self) -> EdgeType {
+        match self {
+            AclRole::Owner => EdgeType::DocumentOwner,
+            AclRole::Reader => EdgeType::DocumentReader
+        }
+    }
+
+    /// Owners may do anything with a document, readers may only read it.
+    pub fn allows(&self, action: DocumentAction) -> bool {
+        match self {
+            AclRole::Owner => true,
+            AclRole::Reader => action == DocumentAction::Read
+        }
+    }
+}
+
+/// A user or organisation with access to a document.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AclEntry {
+    pub entity_id: String,
+    pub role: AclRole
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DocumentAction {
+    Read,
+    Write,
+    ManageAccess
+}
+
+impl std::fmt::Display for DocumentAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            DocumentAction::Read => write!(f, "read"),
+            DocumentAction::Write => write!(f, "write"),
+            DocumentAction::ManageAccess => write!(f, "manage access to")
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Document {
     pub doc_id: String,
+    /// None for documents stored before documents carried metadata.
+    pub metadata: Option<DocumentData>,
     pub owners: Vec<LegalEntity>,
+    pub readers: Vec<LegalEntity>,
+    pub s3: Option<S3Document>,
+    pub checksum: Option<String>,
     pub signatures: Vec<LegalEntity>,
-    pub signature_reqs: Vec<LegalEntity>    
+    pub signature_reqs: Vec<LegalEntity>,
+    /// The earlier document with the same content, when the upload was linked as a duplicate.
+    pub duplicate_of: Option<String>
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
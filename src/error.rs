@@ -0,0 +1,111 @@
+use rusoto_core::RusotoError;
+
+/// Why a session could not be authenticated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthFailure {
+    /// There is no such session.
+    UnknownSession,
+    /// The session never had a login.
+    NotLoggedIn,
+    /// Every login of the session was logged out.
+    LoggedOut,
+    /// The session outlived its lifetime or was idle for too long.
+    Expired
+}
+
+impl std::fmt::Display for AuthFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            AuthFailure::UnknownSession => write!(f, "unknown session"),
+            AuthFailure::NotLoggedIn => write!(f, "not logged in"),
+            AuthFailure::LoggedOut => write!(f, "logged out"),
+            AuthFailure::Expired => write!(f, "expired")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatastoreError {
+    /// The requested vertex has no edges.
+    NotFound(String),
+    /// A vertex id could not be parsed.
+    InvalidVertex(String),
+    /// An edge key did not start with a known edge type.
+    InvalidEdgeType(String),
+    /// The storage backend failed the request.
+    Backend(String),
+    /// The backend throttled the request. Safe to retry after backing off.
+    Throttled(String),
+    /// The backend could not be reached or failed on its side. Safe to retry.
+    Unavailable(String),
+    /// An item could not be converted to or from an edge.
+    Serialization(String),
+    /// A write lost against a concurrent writer.
+    Conflict(String),
+    /// The caller is not allowed to perform the operation.
+    Unauthorized(String),
+    /// The operation is not allowed in the current state of the vertex, e.g. completing an upload twice.
+    InvalidTransition(String),
+    /// An argument was rejected before anything was sent to the backend.
+    InvalidInput(String),
+    /// The stored object does not match what the client reported, e.g. a missing object or another checksum.
+    UploadMismatch(String),
+    /// The owner already has a document with the same content.
+    Duplicate(String),
+    /// The session outlived its lifetime or was idle for too long.
+    Expired(String),
+    /// A session did not authenticate a user.
+    Unauthenticated(AuthFailure)
+}
+
+pub type Result<T> = std::result::Result<T, DatastoreError>;
+
+impl std::fmt::Display for DatastoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            DatastoreError::NotFound(what) => write!(f, "Not found: {}", what),
+            DatastoreError::InvalidVertex(vertex) => write!(f, "Invalid vertex: {}", vertex),
+            DatastoreError::InvalidEdgeType(edge) => write!(f, "Invalid edge type: {}", edge),
+            DatastoreError::Backend(err) => write!(f, "Backend error: {}", err),
+            DatastoreError::Throttled(err) => write!(f, "Throttled: {}", err),
+            DatastoreError::Unavailable(err) => write!(f, "Backend unavailable: {}", err),
+            DatastoreError::Serialization(err) => write!(f, "Serialization error: {}", err),
+            DatastoreError::Conflict(what) => write!(f, "Conflict: {}", what),
+            DatastoreError::Unauthorized(what) => write!(f, "Unauthorized: {}", what),
+            DatastoreError::InvalidTransition(what) => write!(f, "Invalid transition: {}", what),
+            DatastoreError::InvalidInput(what) => write!(f, "Invalid input: {}", what),
+            DatastoreError::UploadMismatch(what) => write!(f, "Upload mismatch: {}", what),
+            DatastoreError::Duplicate(what) => write!(f, "Duplicate: {}", what),
+            DatastoreError::Expired(what) => write!(f, "Expired: {}", what),
+            DatastoreError::Unauthenticated(reason) => write!(f, "Unauthenticated: {}", reason)
+        }
+    }
+}
+
+impl DatastoreError {
+    /// Whether the same request may succeed if sent again, see `RetryPolicy`.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, DatastoreError::Throttled(_) | DatastoreError::Unavailable(_))
+    }
+}
+
+impl std::error::Error for DatastoreError {}
+
+/// Service errors are classified by the caller, which knows their type. Dispatch failures,
+/// 5xx responses and throttling responses rusoto could not parse are transient.
+impl<E: std::error::Error + 'static> From<RusotoError<E>> for DatastoreError {
+    fn from(err: RusotoError<E>) -> DatastoreError {
+        match &err {
+            RusotoError::HttpDispatch(_) => DatastoreError::Unavailable(err.to_string()),
+            RusotoError::Unknown(res) if res.status.is_server_error() => DatastoreError::Unavailable(err.to_string()),
+            RusotoError::Unknown(res) if res.body_as_str().contains("ThrottlingException") => DatastoreError::Throttled(err.to_string()),
+            _ => DatastoreError::Backend(err.to_string())
+        }
+    }
+}
+
+impl From<serde_dynamodb::Error> for DatastoreError {
+    fn from(err: serde_dynamodb::Error) -> DatastoreError {
+        DatastoreError::Serialization(err.to_string())
+    }
+}
@@ -7,45 +7,39 @@ extern crate serde_dynamodb;
 extern crate tokio;
 extern crate chrono;
 extern crate futures;
+extern crate async_trait;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 
 use std::collections::HashMap;
 use rusoto_core::Region;
-use rusoto_dynamodb::{
-    AttributeDefinition, 
-    AttributeValue,
-    CreateTableInput, 
-    DeleteTableInput, 
-    DynamoDb, 
-    DynamoDbClient, 
-    GetItemInput,
-    GlobalSecondaryIndex,
-    KeySchemaElement, 
-    ListTablesInput, 
-    Projection,
-    ProvisionedThroughput,
-    PutItemInput,
-    QueryInput,
-    ScanInput
-};
-use rusoto_credential::{AwsCredentials, EnvironmentProvider, ProvideAwsCredentials};
-use rusoto_s3::util::PreSignedRequest;
-
-use anyhow::{Result, bail};
-
-use log::{info, warn, error};
+
+pub mod config;
+pub mod error;
+
+use log::{info, warn};
 
 use uuid::Uuid;
 
 pub mod domain;
+pub mod store;
+pub mod retry;
+pub mod presign;
+pub mod objects;
 
 use domain::*;
+pub use error::{AuthFailure, DatastoreError, Result};
+pub use config::{BillingMode, GraphDbConfig};
+pub use retry::{NoMetrics, RetryMetrics, RetryPolicy};
+pub use presign::{PresignOptions, S3Config};
+pub use objects::{MemoryObjectStore, ObjectStore, S3ObjectStore};
+pub use store::{GraphStore, DynamoDbStore, MemoryStore, PageKey, PageRequest, QueryOutput, WriteCondition, WriteOp, BATCH_WRITE_LIMIT, TRANSACT_WRITE_LIMIT, TTL_ATTRIBUTE};
 
 use std::collections::HashSet;
+use std::sync::Arc;
 
-use futures::stream::{self, StreamExt};
-use futures::future::join_all;
+use futures::future::try_join_all;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
 
 pub type AwsRegion = rusoto_core::Region;
 
@@ -54,15 +48,156 @@ pub type AwsRegion = rusoto_core::Region;
  TODO Major and important! Remove all sync. Perhaps wait until rusoto migrates to std::futures 
 */
 
+/// The range key of an edge: `{edge_type}|{vertex_a}|{vertex_b}`.
+pub fn edge_key(vertex_a: &Vertex, edge_type: &EdgeType, vertex_b: &Vertex) -> String {
+    format!("{}|{}|{}", edge_type, vertex_a, vertex_b)
+}
+
 pub fn new_edge(vertex_a: &Vertex, edge_type: &EdgeType, vertex_b: &Vertex, data: Option<VertexData>) -> Edge {
     Edge {
         vertex_a: vertex_a.to_string(),
         vertex_b: vertex_b.to_string(),
-        edge: String::from(format!("{}|{}|{}", edge_type, vertex_a, &vertex_b)),
-        data: data
+        edge: edge_key(vertex_a, edge_type, vertex_b),
+        data,
+        version: None,
+        expires_at: None
+    }
+}
+
+/// The edge with its version bumped, and the condition that the stored edge is still at the old version.
+fn next_version(edge: &Edge) -> (Edge, WriteCondition) {
+    let current = edge.version.unwrap_or(0);
+    (Edge { version: Some(current + 1), ..edge.clone() }, WriteCondition::Version(current))
+}
+
+/// Where a session is: it starts out not logged in, every login supersedes the one before it,
+/// a logout ends the active login, and any of them may expire.
+enum SessionState<'a> {
+    NotLoggedIn,
+    /// The login vertex and data of the active login.
+    LoggedIn(&'a str, &'a SessionData),
+    LoggedOut,
+    Expired
+}
+
+/// The edges of a session, see `GraphDb::session_edges`.
+struct SessionEdges {
+    session_id: String,
+    session_self: Option<Edge>,
+    users: Vec<String>,
+    /// Login vertex and data of every `session_login` edge.
+    logins: Vec<(String, SessionData)>,
+    /// Login vertices with a `session_logout` edge, and when they were logged out.
+    logged_out: HashMap<String, Option<String>>
+}
+
+impl SessionEdges {
+    fn session_data(&self) -> Option<&SessionData> {
+        match self.session_self.as_ref().and_then(|edge| edge.data.as_ref()) {
+            Some(VertexData::SessionData(data)) => Some(data),
+            _ => None
+        }
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.session_data().map(|data| data.is_expired(now)).unwrap_or(false)
+    }
+
+    fn edges_expire_at(&self) -> Option<i64> {
+        self.session_self.as_ref().and_then(edges_expire_at)
+    }
+
+    /// Logins without a logout. Only sessions logged in before logins superseded each other have more than one.
+    fn active_logins(&self) -> impl Iterator<Item = &(String, SessionData)> {
+        self.logins.iter().filter(move |(session_login_id, login)| login.logout.is_none() && !self.logged_out.contains_key(session_login_id))
+    }
+
+    fn state(&self, now: DateTime<Utc>) -> SessionState<'_> {
+        if self.is_expired(now) {
+            return SessionState::Expired;
+        }
+        if self.logins.is_empty() {
+            return SessionState::NotLoggedIn;
+        }
+        match self.active_logins().max_by(|(_, a), (_, b)| a.login.cmp(&b.login)) {
+            Some((session_login_id, login)) => SessionState::LoggedIn(session_login_id, login),
+            None => SessionState::LoggedOut
+        }
+    }
+
+    /// A `session_logout` edge to the login vertex of each of `logins`.
+    fn logout_ops(&self, logins: &[&(String, SessionData)], now: DateTime<Utc>, expires_at: Option<i64>) -> Result<Vec<WriteOp>> {
+        let session_vertex: Vertex = self.session_id.parse()?;
+        let logout = Some(now.to_rfc3339());
+        logins.iter().map(|(session_login_id, login)| Ok(WriteOp::Put(Edge { expires_at, ..new_edge(
+            &session_vertex,
+            &EdgeType::SessionLogout,
+            &session_login_id.parse::<Vertex>()?,
+            Some(VertexData::SessionData(SessionData{logout: logout.clone(), ..login.clone()}))
+        )}))).collect()
+    }
+
+    /// The user of a login. Logins written before login edges carried the user have a single `session_user` edge.
+    fn login_user(&self, login: &SessionData) -> Option<String> {
+        match (&login.user_id, self.users.as_slice()) {
+            (Some(user_id), _) => Some(user_id.clone()),
+            (None, [user_id]) => Some(user_id.clone()),
+            _ => None
+        }
+    }
+
+    fn authenticated(&self, session_login_id: &str, login: &SessionData, user: User) -> AuthenticatedSession {
+        AuthenticatedSession {
+            session_id: self.session_id.clone(),
+            session_login_id: String::from(session_login_id),
+            user,
+            login: login.login.clone(),
+            auth_data: login.auth_data.clone(),
+            expires_at: self.session_data().and_then(|data| data.expires_at()).map(|expires_at| expires_at.to_rfc3339())
+        }
+    }
+}
+
+/// `now` plus `lifetime`, unless that is past what a timestamp can hold.
+fn deadline(now: DateTime<Utc>, lifetime: std::time::Duration) -> Option<DateTime<Utc>> {
+    chrono::Duration::from_std(lifetime).ok().and_then(|lifetime| now.checked_add_signed(lifetime))
+}
+
+/// Time to live of the other edges of the session `session_self` belongs to. They only have to outlive
+/// `session_self`, as a session whose `session_self` is gone is gone: with an absolute lifetime they expire
+/// when it ends, and with only an idle timeout one timeout after `session_self`, so that `touch_session`
+/// rewrites them about once per timeout rather than on every touch.
+fn edges_expire_at(session_self: &Edge) -> Option<i64> {
+    let data = match &session_self.data {
+        Some(VertexData::SessionData(data)) => data,
+        _ => return None
+    };
+    match (data.lifetime_ends_at(), data.expires_at(), data.idle_timeout) {
+        (Some(ends_at), _, _) => Some(ends_at.timestamp()),
+        (None, Some(expires_at), Some(idle_timeout)) => Some(expires_at.timestamp() + idle_timeout as i64),
+        _ => None
     }
 }
 
+/// The `session_self` edge marked as used at `now`, with its expiry moved along. Fails if the session already expired.
+fn touched_session(edge: &Edge, now: DateTime<Utc>) -> Result<(Edge, WriteCondition)> {
+    let data = match &edge.data {
+        Some(VertexData::SessionData(data)) => data,
+        _ => return Ok(next_version(edge))
+    };
+    if data.is_expired(now) {
+        return Err(DatastoreError::Expired(edge.vertex_a.clone()));
+    }
+    let data = SessionData {
+        last_active: Some(now.to_rfc3339()),
+        ..data.clone()
+    };
+    Ok(next_version(&Edge {
+        expires_at: data.expires_at().map(|expires_at| expires_at.timestamp()),
+        data: Some(VertexData::SessionData(data)),
+        ..edge.clone()
+    }))
+}
 
 fn format_row(label: &str) -> String {
     format!("<tr><td colspan=\"2\" align=\"left\">{}</td></tr>", label)
@@ -76,7 +211,7 @@ fn format_row_attribute(key: &str, val: Option<String>) -> String {
 }
 
 fn dot_format_s3_document(data: &S3Document) -> String {
-    let mut res = format_row(&"<b>S3Document</b>");
+    let mut res = format_row("<b>S3Document</b>");
     res.push_str(&format_row_attribute("bucket", Some(data.bucket.clone())));
     res.push_str(&format_row_attribute("key", Some(data.key.clone())));
     res.to_string()
@@ -102,180 +237,425 @@ fn dot_format_session_data(data: &SessionData) -> String {
     res.push_str(&format_row_attribute("created", data.created.clone()));
     res.push_str(&format_row_attribute("login", data.login.clone()));
     res.push_str(&format_row_attribute("logout", data.logout.clone()));
-    res.push_str(&format_row_attribute("auth_data", data.auth_data.clone()));
+    res.push_str(&format_row_attribute("auth_data", data.auth_data.as_ref().map(|auth_data| auth_data.to_string())));
     
     res.to_string()
 }
 
+fn dot_format_organisation_data(data: &OrganisationData) -> String {
+    let mut res = format_row("<b>OrganisationData</b>");
+    res.push_str(&format_row_attribute("org_no", Some(data.org_no.clone())));
+    res.push_str(&format_row_attribute("name", Some(data.name.clone())));
+    res.to_string()
+}
+
+fn dot_format_membership_data(data: &MembershipData) -> String {
+    let mut res = format_row("<b>MembershipData</b>");
+    res.push_str(&format_row_attribute("role", Some(data.role.clone())));
+    res.to_string()
+}
+
+fn dot_format_sign_request_data(data: &SignRequestData) -> String {
+    let mut res = format_row("<b>SignRequestData</b>");
+    res.push_str(&format_row_attribute("status", Some(data.status.to_string())));
+    res.push_str(&format_row_attribute("requested", Some(data.requested.clone())));
+    res.push_str(&format_row_attribute("due", data.due.clone()));
+    res.push_str(&format_row_attribute("message", data.message.clone()));
+    res.to_string()
+}
+
+fn dot_format_document_data(data: &DocumentData) -> String {
+    let mut res = format_row("<b>DocumentData</b>");
+    res.push_str(&format_row_attribute("status", Some(data.status.to_string())));
+    res.push_str(&format_row_attribute("title", data.title.clone()));
+    res.push_str(&format_row_attribute("content_type", data.content_type.clone()));
+    res.push_str(&format_row_attribute("size", data.size.map(|size| size.to_string())));
+    res.push_str(&format_row_attribute("modified", Some(data.modified.clone())));
+    res.to_string()
+}
+
+fn dot_format_signature_data(data: &SignatureData) -> String {
+    let mut res = format_row("<b>SignatureData</b>");
+    res.push_str(&format_row_attribute("checksum", Some(data.checksum.clone())));
+    res.push_str(&format_row_attribute("signed", Some(data.signed.clone())));
+    res.push_str(&format_row_attribute("signed_by", Some(data.signed_by.clone())));
+    res.to_string()
+}
+
 fn format_vertex(data: &VertexData) -> String {
     match data { // TODO strange strings in write
         VertexData::S3Document(data) => dot_format_s3_document(data),
         VertexData::String(data) => dot_format_string(data),
         VertexData::UserData(data) => dot_format_user_data(data),
         VertexData::SessionData(data) => dot_format_session_data(data),
+        VertexData::OrganisationData(data) => dot_format_organisation_data(data),
+        VertexData::MembershipData(data) => dot_format_membership_data(data),
+        VertexData::SignRequestData(data) => dot_format_sign_request_data(data),
+        VertexData::SignatureData(data) => dot_format_signature_data(data),
+        VertexData::DocumentData(data) => dot_format_document_data(data),
         VertexData::None => String::new()
     }
 }
 
 
-pub struct GraphDb {
-    pub client: DynamoDbClient
+/// How queries treat items that cannot be decoded into an `Edge`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodeMode {
+    /// Fail the query with `DatastoreError::Serialization`.
+    Strict,
+    /// Skip corrupt items and log each of them as a warning.
+    Lenient
+}
+
+/// What `upload_document_completed` does when an owner of the document already owns another document with the same checksum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Accept the upload as a document of its own.
+    Allow,
+    /// Fail with `DatastoreError::Duplicate`, leaving the document pending.
+    Reject,
+    /// Accept the upload and link it to the earlier document with a `doc_duplicate` edge.
+    Link
+}
+
+/// How long sessions live. Both limits are off by default, so sessions never expire.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SessionLifetime {
+    /// Longest a session lives after it was created, however much it is used.
+    pub absolute: Option<std::time::Duration>,
+    /// Longest a session may go unused, see `GraphDb::touch_session`.
+    pub idle: Option<std::time::Duration>
+}
+
+impl SessionLifetime {
+    pub fn new() -> SessionLifetime {
+        SessionLifetime::default()
+    }
+
+    pub fn absolute(self, absolute: std::time::Duration) -> SessionLifetime {
+        SessionLifetime {
+            absolute: Some(absolute),
+            ..self
+        }
+    }
+
+    pub fn idle(self, idle: std::time::Duration) -> SessionLifetime {
+        SessionLifetime {
+            idle: Some(idle),
+            ..self
+        }
+    }
+}
+
+/// One page of results and the continuation token for the next page, if there may be one.
+#[derive(Clone, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_token: Option<String>
+}
+
+#[derive(Clone, Debug)]
+pub struct EdgeWriteFailure {
+    pub edge: Edge,
+    pub error: DatastoreError
+}
+
+/// Outcome of `GraphDb::store_edges`: how many edges were written and which were not.
+#[derive(Clone, Debug, Default)]
+pub struct BatchWriteReport {
+    pub written: usize,
+    pub failed: Vec<EdgeWriteFailure>
+}
+
+impl BatchWriteReport {
+    /// Turn the report into an error if any edge failed, using the error of the first failure.
+    pub fn into_result(self) -> Result<usize> {
+        match self.failed.into_iter().next() {
+            Some(failure) => Err(failure.error),
+            None => Ok(self.written)
+        }
+    }
 }
 
-impl GraphDb {
+pub struct GraphDb<S: GraphStore = DynamoDbStore> {
+    pub store: S,
+    pub decode_mode: DecodeMode,
+    pub retry_policy: RetryPolicy,
+    pub s3: S3Config,
+    /// Where uploads are checked before `upload_document_completed` accepts them. Unchecked when `None`.
+    pub upload_verification: Option<Arc<dyn ObjectStore>>,
+    pub duplicate_policy: DuplicatePolicy,
+    pub session_lifetime: SessionLifetime
+}
 
+impl GraphDb<DynamoDbStore> {
 
     pub fn new(region: Region) -> GraphDb {
-        info!("Create dynamodb client");
+        GraphDb::without_upload_verification(&GraphDbConfig::new().region(region))
+    }
+
+    pub fn new_with_region(region_name: &str, endpoint: &str) -> GraphDb {
+        GraphDb::without_upload_verification(&GraphDbConfig::new().endpoint(region_name, endpoint))
+    }
+
+    /// Fails with `DatastoreError::Backend` if upload verification is on and its S3 client cannot be created.
+    pub fn from_config(config: GraphDbConfig) -> Result<GraphDb> {
+        let db = GraphDb::without_upload_verification(&config);
+        if config.verify_uploads {
+            Ok(db.with_upload_verification(Arc::new(S3ObjectStore::from_config(&config.s3_config(), config.retry_policy.clone())?)))
+        } else {
+            Ok(db)
+        }
+    }
+
+    fn without_upload_verification(config: &GraphDbConfig) -> GraphDb {
+        GraphDb::with_store(DynamoDbStore::from_config(config))
+            .with_decode_mode(config.decode_mode)
+            .with_retry_policy(config.retry_policy.clone())
+            .with_s3(config.s3_config())
+            .with_duplicate_policy(config.duplicate_policy)
+            .with_session_lifetime(config.session_lifetime)
+    }
+
+    pub async fn create_table(&self) -> Result<()> {
+        self.store.create_table().await
+    }
+
+    pub async fn enable_ttl(&self) -> Result<()> {
+        self.store.enable_ttl().await
+    }
+}
+
+impl<S: GraphStore> GraphDb<S> {
+
+    pub fn with_store(store: S) -> GraphDb<S> {
         GraphDb {
-            client: DynamoDbClient::new(region)
+            store,
+            decode_mode: DecodeMode::Strict,
+            retry_policy: RetryPolicy::default(),
+            s3: S3Config::default(),
+            upload_verification: None,
+            duplicate_policy: DuplicatePolicy::Allow,
+            session_lifetime: SessionLifetime::default()
         }
     }
 
-    pub fn new_with_region(region_name: &str, endpoint: &str) -> GraphDb {
-        let region = Region::Custom {
-                name: region_name.to_owned(),
-                endpoint: endpoint.to_owned(),
-        };
-        info!("Create dynamodb client");
+    pub fn with_s3(self, s3: S3Config) -> GraphDb<S> {
         GraphDb {
-            client: DynamoDbClient::new(region)
+            s3,
+            ..self
         }
     }
 
-    pub async fn create_table(&self) -> Result<()> {
-        let create_res = self.client.create_table(CreateTableInput{
-            table_name: String::from("insignia-docs"),
-            key_schema: vec![
-                KeySchemaElement {
-                    attribute_name: "vertex_a".into(),
-                    key_type: "HASH".into(),
-                }, 
-                KeySchemaElement {
-                    attribute_name: "edge".into(),
-                    key_type: "RANGE".into(),
+    /// Check that uploads exist in `objects` with the reported checksum before accepting them.
+    pub fn with_upload_verification(self, objects: Arc<dyn ObjectStore>) -> GraphDb<S> {
+        GraphDb {
+            upload_verification: Some(objects),
+            ..self
+        }
+    }
+
+    pub fn with_duplicate_policy(self, duplicate_policy: DuplicatePolicy) -> GraphDb<S> {
+        GraphDb {
+            duplicate_policy,
+            ..self
+        }
+    }
+
+    pub fn with_session_lifetime(self, session_lifetime: SessionLifetime) -> GraphDb<S> {
+        GraphDb {
+            session_lifetime,
+            ..self
+        }
+    }
+
+    /// Policy for retrying edges a batch write left unprocessed. The DynamoDB store retries its own calls.
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> GraphDb<S> {
+        GraphDb {
+            retry_policy,
+            ..self
+        }
+    }
+
+    pub fn with_decode_mode(self, decode_mode: DecodeMode) -> GraphDb<S> {
+        GraphDb {
+            decode_mode,
+            ..self
+        }
+    }
+
+    fn accept(&self, output: QueryOutput) -> Result<Vec<Edge>> {
+        if output.corrupt.is_empty() {
+            return Ok(output.edges);
+        }
+        match self.decode_mode {
+            DecodeMode::Strict => {
+                let corrupt = &output.corrupt[0];
+                Err(DatastoreError::Serialization(format!("{} corrupt edge(s), first {}", output.corrupt.len(), corrupt)))
+            },
+            DecodeMode::Lenient => {
+                for corrupt in &output.corrupt {
+                    warn!("Skipping corrupt edge {}", corrupt);
                 }
-            ],
-            attribute_definitions: vec![
-                AttributeDefinition {
-                    attribute_name: "vertex_a".into(),
-                    attribute_type: "S".into(),
-                },
-                AttributeDefinition {
-                    attribute_name: "vertex_b".into(),
-                    attribute_type: "S".into(),
+                Ok(output.edges)
+            }
+        }
+    }
+
+    fn stream_edges<'a>(&'a self, vertex: &'a str, edge_prefix: Option<&'a str>, vertex_b_index: bool, first: PageRequest) -> BoxStream<'a, Result<Edge>> {
+        stream::unfold(Some(first), move |page| async move {
+            let page = page?;
+            let output = if vertex_b_index {
+                self.store.query_edges_to(vertex, edge_prefix, &page).await
+            } else {
+                self.store.query_edges_from(vertex, edge_prefix, &page).await
+            };
+            let (edges, next) = match output {
+                Ok(mut output) => {
+                    let next = output.next.take().map(|key| PageRequest { start: Some(key), ..page.clone() });
+                    match self.accept(output) {
+                        Ok(edges) => (edges.into_iter().map(Ok).collect(), next),
+                        Err(err) => (vec![Err(err)], None)
+                    }
                 },
-                AttributeDefinition {
-                    attribute_name: "edge".into(),
-                    attribute_type: "S".into(),
-                }
-            ],
-            global_secondary_indexes: Some(vec![
-                GlobalSecondaryIndex{
-                    index_name: "index-vertex_b_edges".into(),
-                    key_schema: vec![
-                        KeySchemaElement {
-                            attribute_name: "vertex_b".into(),
-                            key_type: "HASH".into(),
-                        }, 
-                        KeySchemaElement {
-                            attribute_name: "edge".into(),
-                            key_type: "RANGE".into(),
-                        }
-                    ],
-                    projection: Projection {
-                        non_key_attributes: None,
-                        projection_type: Some("ALL".into())
+                Err(err) => (vec![Err(err)], None)
+            };
+            Some((stream::iter(edges), next))
+        }).flatten().boxed()
+    }
 
-                    },
-                    provisioned_throughput: Some(ProvisionedThroughput {
-                        read_capacity_units: 1,
-                        write_capacity_units: 1,
-                    })
-                }
-            ]),
-            provisioned_throughput: Some(ProvisionedThroughput {
-                read_capacity_units: 1,
-                write_capacity_units: 1,
-            }),
-            ..CreateTableInput::default()
-        }).await?;
-        
-        match create_res.table_description {
-            Some(desc) => println!("Created table {:?}", desc),
-            None => println!("Created table, no table description")
+    /// Edges from `vertex_a`, fetching one page at a time as the stream is polled.
+    pub fn stream_edges_from<'a>(&'a self, vertex_a: &'a str) -> BoxStream<'a, Result<Edge>> {
+        self.stream_edges(vertex_a, None, false, PageRequest::default())
+    }
+
+    /// Edges to `vertex_b` through the `vertex_b` index, fetching one page at a time as the stream is polled.
+    pub fn stream_edges_to<'a>(&'a self, vertex_b: &'a str) -> BoxStream<'a, Result<Edge>> {
+        self.stream_edges(vertex_b, None, true, PageRequest::default())
+    }
+
+    async fn edges_from(&self, vertex_a: &str, edge_prefix: Option<&str>) -> Result<Vec<Edge>> {
+        self.stream_edges(vertex_a, edge_prefix, false, PageRequest::default()).try_collect().await
+    }
+
+    async fn edges_to(&self, vertex_b: &str, edge_prefix: Option<&str>) -> Result<Vec<Edge>> {
+        self.stream_edges(vertex_b, edge_prefix, true, PageRequest::default()).try_collect().await
+    }
+
+    /// Edges from `vertex_a` as last written, for reads whose versions a conditional write depends on.
+    async fn consistent_edges_from(&self, vertex_a: &str, edge_prefix: Option<&str>) -> Result<Vec<Edge>> {
+        self.stream_edges(vertex_a, edge_prefix, false, PageRequest::consistent()).try_collect().await
+    }
+
+    /// One page of edges from `vertex_a`, starting after the continuation token of the previous page.
+    pub async fn get_edges_page(&self, vertex_a: &str, edge_prefix: Option<&str>, limit: i64, next_token: Option<&str>) -> Result<Page<Edge>> {
+        let page = PageRequest {
+            limit: Some(limit),
+            start: next_token.map(PageKey::from_token).transpose()?,
+            ..PageRequest::default()
         };
-            
-        Ok(())
+        let mut output = self.store.query_edges_from(vertex_a, edge_prefix, &page).await?;
+        let next = output.next.take();
+        Ok(Page {
+            items: self.accept(output)?,
+            next_token: next.map(|key| key.to_token())
+        })
     }
 
     pub async fn store_edge(&self, edge: &Edge) -> Result<()> {
-        // todo retries etc due to documentation
-        println!("Store edge {} {} {:?}", edge.vertex_a, edge.vertex_b, edge.data);
-        let put_res = self.client.put_item(PutItemInput{
-            condition_expression: None,
-            conditional_operator: None,
-            expression_attribute_names: None,
-            expression_attribute_values: None,
-            expected: None,
-            item: serde_dynamodb::to_hashmap(&edge).unwrap(),
-            return_values: None,
-            return_consumed_capacity: None,
-            return_item_collection_metrics: None,
-            table_name: String::from("insignia-docs")
-        }).await?;
-        info!("ok! {:?}", put_res);
-        Ok(())
+        self.store.put_edge(edge).await
     }
 
-    pub async fn get_vertex_with_edges(&self, vertex_id: &str) -> Result<Vec<Edge>> {
-        // todo retries on error?? 
-        let query_key_vertex_a: HashMap<String, AttributeValue> =
-            [(String::from(":vertex_a"), AttributeValue{        
-                    s:Some(String::from(vertex_id)),
-                    ..Default::default()
-                })]
-            .iter().cloned().collect();
-
-        let mut edges_from_vertex_a : Vec<Edge> = match self.client.query(
-            QueryInput{
-                table_name: String::from("insignia-docs"),
-                key_condition_expression: Some(String::from("vertex_a = :vertex_a")),
-                expression_attribute_values: Some(query_key_vertex_a),
-                .. QueryInput::default()
-            }).await {
-                Ok(res) => {
-                    res.items.unwrap_or_else(|| vec![]).into_iter().map(|item| serde_dynamodb::from_hashmap(item).unwrap()).collect()
-                },
-                Err(err) =>  {
-                    error!("Error query{:?}", err);
-                    vec![]
+    /// Write edges in batches of `BATCH_WRITE_LIMIT`, retrying unprocessed edges with exponential backoff.
+    ///
+    /// Edges with the same key are written once, the last one wins. Failures are reported per edge
+    /// instead of failing the call, so check the report or use `BatchWriteReport::into_result`.
+    pub async fn store_edges(&self, edges: &[Edge]) -> Result<BatchWriteReport> {
+        let mut unique: Vec<Edge> = Vec::new();
+        let mut seen: HashSet<(&str, &str)> = HashSet::new();
+        for edge in edges.iter().rev() {
+            if seen.insert((&edge.vertex_a, &edge.edge)) {
+                unique.push(edge.clone());
+            }
+        }
+        unique.reverse();
+
+        let mut report = BatchWriteReport::default();
+        for chunk in unique.chunks(BATCH_WRITE_LIMIT) {
+            let mut pending = chunk.to_vec();
+            let mut attempt = 0;
+            while !pending.is_empty() {
+                let offered = pending.len();
+                match self.store.put_edges(&pending).await {
+                    Ok(unprocessed) => {
+                        report.written += offered - unprocessed.len();
+                        pending = unprocessed;
+                    },
+                    Err(err) => {
+                        report.failed.extend(pending.drain(..).map(|edge| EdgeWriteFailure { edge, error: err.clone() }));
+                        break;
+                    }
                 }
-        };
-
-        let query_key_vertex_b: HashMap<String, AttributeValue> =
-            [(String::from(":vertex_b"), AttributeValue{        
-                    s:Some(String::from(vertex_id)),
-                    ..Default::default()
-                })]
-            .iter().cloned().collect();
-
-        let mut edges_from_vertex_b : Vec<Edge> = match self.client.query(
-            QueryInput{
-                table_name: String::from("insignia-docs"),
-                key_condition_expression: Some(String::from("vertex_b = :vertex_b")),
-                index_name: Some(String::from("index-vertex_b_edges")),
-                expression_attribute_values: Some(query_key_vertex_b),
-                .. QueryInput::default()
-            }).await {
-                Ok(res) => {
-                    res.items.unwrap_or_else(|| vec![]).into_iter().map(|item| serde_dynamodb::from_hashmap(item).unwrap()).collect()
-                },
-                Err(err) =>  {
-                    error!("Error query{:?}", err);
-                    vec![]
+                if pending.is_empty() {
+                    break;
+                }
+                attempt += 1;
+                self.retry_policy.metrics.on_throttle("BatchWriteItem");
+                if attempt >= self.retry_policy.max_attempts {
+                    self.retry_policy.metrics.on_give_up("BatchWriteItem", attempt);
+                    let error = DatastoreError::Throttled(format!("Edge left unprocessed after {} attempts", attempt));
+                    report.failed.extend(pending.drain(..).map(|edge| EdgeWriteFailure { edge, error: error.clone() }));
+                    break;
                 }
+                let delay = self.retry_policy.delay(attempt);
+                warn!("{} edge(s) unprocessed, retrying in {:?}", pending.len(), delay);
+                self.retry_policy.metrics.on_retry("BatchWriteItem", attempt, delay);
+                tokio::time::delay_for(delay).await;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Apply all operations or none of them, in a single request of at most `TRANSACT_WRITE_LIMIT` operations.
+    pub async fn transact(&self, ops: &[WriteOp]) -> Result<()> {
+        self.store.transact_write(ops).await
+    }
+
+    /// Store all edges or none of them.
+    pub async fn store_edges_atomic(&self, edges: &[Edge]) -> Result<()> {
+        let ops: Vec<WriteOp> = edges.iter().cloned().map(WriteOp::Put).collect();
+        self.transact(&ops).await
+    }
+
+    /// The edge stored under `vertex_a` and the full `edge` key, if any, read with a strongly consistent read.
+    pub async fn get_edge(&self, vertex_a: &str, edge: &str) -> Result<Option<Edge>> {
+        let edges = self.consistent_edges_from(vertex_a, Some(edge)).await?;
+        Ok(edges.into_iter().find(|item| item.edge == edge))
+    }
+
+    /// Store an edge that must not exist yet. Returns the stored edge, at version 1.
+    pub async fn create_edge(&self, edge: &Edge) -> Result<Edge> {
+        let edge = Edge {
+            version: Some(1),
+            ..edge.clone()
         };
+        self.store.put_edge_if(&edge, WriteCondition::NotExists).await?;
+        Ok(edge)
+    }
+
+    /// Replace an edge read earlier, provided nobody has updated it since.
+    ///
+    /// The stored version must equal `edge.version`; a stale edge fails with `DatastoreError::Conflict`,
+    /// and the caller can read the edge again and retry. Returns the stored edge with its new version.
+    pub async fn update_edge(&self, edge: &Edge) -> Result<Edge> {
+        let (updated, condition) = next_version(edge);
+        self.store.put_edge_if(&updated, condition).await?;
+        Ok(updated)
+    }
+
+    pub async fn get_vertex_with_edges(&self, vertex_id: &str) -> Result<Vec<Edge>> {
+        let mut edges_from_vertex_a = self.edges_from(vertex_id, None).await?;
+        let mut edges_from_vertex_b = self.edges_to(vertex_id, None).await?;
 
         for item in &edges_from_vertex_a {
             info!("Edge {}  {} {} {:?}", item.vertex_a, item.edge, item.vertex_b, item.data);
@@ -293,7 +673,7 @@ impl GraphDb {
 
         let mut vertex_link_ids : HashSet<String> = HashSet::new();
 
-        let edges = self.get_vertex_with_edges(&vertex_id).await?;
+        let edges = self.get_vertex_with_edges(vertex_id).await?;
         for edge in edges {
             let key = edge.vertex_b;
             let key_a = edge.vertex_a;
@@ -310,12 +690,10 @@ impl GraphDb {
                 }
                 
             }; 
-            if !render_verticies.contains_key(&key_a) {
-                render_verticies.insert(key_a.clone(), VertexData::String(String::from("")));
-            }
+            render_verticies.entry(key_a.clone()).or_insert_with(|| VertexData::String(String::from("")));
             render_edges.push(format!(
                 r#"{} -> {}"#, 
-                &key_a.to_string().replace("-", "_").replace(".", "").replace("+", "").replace("@", ""), 
+                &key_a.replace("-", "_").replace(".", "").replace("+", "").replace("@", ""),
                 &key.replace("-", "_").replace(".", "").replace("+", "").replace("@", "")
             )); 
         }
@@ -333,73 +711,270 @@ impl GraphDb {
         {}
         </table>>];
     "#, &key.replace("-", "_").replace(".", "").replace("+", "").replace("@", ""),
-     &format_vertex(&value), 
-     &format_row(&key))).collect::<Vec<String>>().join("\n"),
+     &format_vertex(value),
+     &format_row(key))).collect::<Vec<String>>().join("\n"),
             render_edges.join("\n")),
             format!("<ul>{}</ul>", &vertex_links)))
     }
 
-    pub async fn upload_document_url(&self, bucket:&str, user_id: &Vertex) -> Result<String> {
-        let credentials = EnvironmentProvider::default().credentials().await.unwrap();
+    /// Create a document in `PendingUpload` owned by `owner`, and return its id.
+    pub async fn new_document(&self, owner: &Vertex, title: Option<&str>, content_type: Option<&str>) -> Result<String> {
         let doc_id = Vertex::Document(Uuid::new_v4().to_hyphenated().to_string());
-        let edge = new_edge(&user_id, &EdgeType::DocumentOwner, &doc_id, Some(VertexData::String(String::from("some document data"))));
+        let now = Utc::now().to_rfc3339();
+        let data = DocumentData {
+            title: title.map(String::from),
+            content_type: content_type.map(String::from),
+            size: None,
+            created: now.clone(),
+            modified: now,
+            status: DocumentStatus::PendingUpload
+        };
+        let doc_self = Edge {
+            version: Some(1),
+            ..new_edge(&doc_id, &EdgeType::DocumentSelf, &doc_id, Some(VertexData::DocumentData(data)))
+        };
+        self.transact(&[
+            WriteOp::PutIf(doc_self, WriteCondition::NotExists),
+            WriteOp::Put(new_edge(owner, &EdgeType::DocumentOwner, &doc_id, None))
+        ]).await?;
+        Ok(doc_id.to_string())
+    }
+
+    /// The `doc_self` edge of a document.
+    async fn document_self(&self, doc_vertex: &Vertex) -> Result<Edge> {
+        let key = edge_key(doc_vertex, &EdgeType::DocumentSelf, doc_vertex);
+        self.get_edge(&doc_vertex.to_string(), &key).await?
+            .ok_or_else(|| DatastoreError::NotFound(doc_vertex.to_string()))
+    }
 
-        let req = rusoto_s3::PutObjectRequest {
-            bucket: String::from(bucket),
-            key: format!("uploads/{}", &doc_id.to_string()),
-            ..Default::default()
+    /// Move a document to `status` together with `ops`, unless the transition is not allowed or someone else moved it first.
+    /// `update` may change the rest of the document data in the same write.
+    async fn transition_document<F>(&self, doc_vertex: &Vertex, status: DocumentStatus, ops: Vec<WriteOp>, update: F) -> Result<DocumentData>
+    where
+        F: FnOnce(DocumentData) -> DocumentData + Send
+    {
+        let edge = self.document_self(doc_vertex).await?;
+        let data = match &edge.data {
+            Some(VertexData::DocumentData(data)) => data.clone(),
+            _ => return Err(DatastoreError::InvalidTransition(format!("{} has no lifecycle status", doc_vertex)))
         };
-        self.store_edge(&edge).await?;
-        
-        let presigned_url = req.get_presigned_url(&rusoto_core::Region::EuNorth1, &credentials, &Default::default());
-        Ok(presigned_url) 
+        if !data.status.can_become(status) {
+            return Err(DatastoreError::InvalidTransition(format!("{} is {} and cannot become {}", doc_vertex, data.status, status)));
+        }
+        let data = DocumentData {
+            status,
+            modified: Utc::now().to_rfc3339(),
+            ..update(data)
+        };
+        let (doc_self, unchanged) = next_version(&Edge {
+            data: Some(VertexData::DocumentData(data.clone())),
+            ..edge
+        });
+        let mut all_ops = vec![WriteOp::PutIf(doc_self, unchanged)];
+        all_ops.extend(ops);
+        self.transact(&all_ops).await?;
+        Ok(data)
+    }
+
+    /// Move a document to `status`, e.g. to archive it. Fails with `DatastoreError::InvalidTransition` if the
+    /// document cannot go there from its current status.
+    pub async fn set_document_status(&self, doc_id: &str, status: DocumentStatus) -> Result<DocumentData> {
+        self.transition_document(&doc_id.parse()?, status, Vec::new(), |data| data).await
+    }
+
+    /// Write a sign request, and any `extra` writes, in one transaction with the `doc_self` edge, keeping the
+    /// status of a document that is being signed in line with its sign requests. `doc_self` is written even
+    /// when the status stays, so two concurrent changes to sign requests cannot both miss each other.
+    async fn write_sign_request(&self, doc_vertex: &Vertex, request: Edge, condition: WriteCondition, extra: Vec<WriteOp>) -> Result<()> {
+        let doc_id = doc_vertex.to_string();
+        let (doc_self, stored) = futures::try_join!(
+            self.document_self(doc_vertex),
+            self.consistent_edges_from(&doc_id, Some("doc_signreq"))
+        )?;
+        let mut ops = Vec::new();
+        if let Some(VertexData::DocumentData(data)) = &doc_self.data {
+            if [DocumentStatus::Uploaded, DocumentStatus::AwaitingSignatures, DocumentStatus::Signed].contains(&data.status) {
+                let active: Vec<SignRequest> = stored.iter()
+                    .filter(|edge| edge.edge != request.edge)
+                    .chain(std::iter::once(&request))
+                    .map(SignRequest::from_edge)
+                    .filter(|request| request.status != SignRequestStatus::Cancelled)
+                    .collect();
+                let target = if active.is_empty() {
+                    DocumentStatus::Uploaded
+                } else if active.iter().all(|request| request.status == SignRequestStatus::Signed) {
+                    DocumentStatus::Signed
+                } else {
+                    DocumentStatus::AwaitingSignatures
+                };
+                let data = if target == data.status {
+                    data.clone()
+                } else if data.status.can_become(target) {
+                    DocumentData { status: target, modified: Utc::now().to_rfc3339(), ..data.clone() }
+                } else {
+                    return Err(DatastoreError::InvalidTransition(format!("{} is {} and cannot become {}", doc_vertex, data.status, target)));
+                };
+                let (doc_self, unchanged) = next_version(&Edge {
+                    data: Some(VertexData::DocumentData(data)),
+                    ..doc_self.clone()
+                });
+                ops.push(WriteOp::PutIf(doc_self, unchanged));
+            }
+        }
+        ops.push(WriteOp::PutIf(request, condition));
+        ops.extend(extra);
+        self.transact(&ops).await
+    }
+
+    /// Create a document owned by `user_id` and return its id with a URL to upload it to `uploads/{doc_id}` in `bucket`.
+    pub async fn upload_document_url(&self, bucket:&str, user_id: &Vertex, options: &PresignOptions) -> Result<(String, String)> {
+        options.validate()?;
+        let credentials = self.s3.resolve_credentials().await?;
+        let doc_id = self.new_document(user_id, None, options.content_type.as_deref()).await?;
+        let presigned_url = presign::presigned_upload_url(&self.s3, &credentials, bucket, &format!("uploads/{}", &doc_id), options)?;
+        Ok((doc_id, presigned_url))
+    }
+
+    /// A URL to download the stored content of a document, valid for `expires_in`.
+    pub async fn download_document_url(&self, doc_id: &str, expires_in: std::time::Duration) -> Result<String> {
+        let s3 = match self.edges_from(doc_id, Some("doc_location_s3")).await?.into_iter().next().and_then(|edge| edge.data) {
+            Some(VertexData::S3Document(s3)) => s3,
+            _ => return Err(DatastoreError::NotFound(format!("S3 location of {}", doc_id)))
+        };
+        let credentials = self.s3.resolve_credentials().await?;
+        presign::presigned_download_url(&self.s3, &credentials, &s3.bucket, &s3.key, expires_in)
     }
 
 
+    /// Check that the object at `s3_key` is where `upload_document_url` asked for it and has the reported checksum.
+    async fn verify_upload(&self, objects: &dyn ObjectStore, doc_id: &Vertex, s3_bucket: &str, s3_key: &str, sha256: &str) -> Result<(String, u64)> {
+        let expected_key = format!("uploads/{}", doc_id);
+        if s3_key != expected_key {
+            return Err(DatastoreError::UploadMismatch(format!("{} was uploaded to {}, expected {}", doc_id, s3_key, expected_key)));
+        }
+        let size = match objects.head_object(s3_bucket, s3_key).await? {
+            Some(size) => size,
+            None => return Err(DatastoreError::UploadMismatch(format!("No object at {}/{}", s3_bucket, s3_key)))
+        };
+        match objects.object_sha256(s3_bucket, s3_key).await? {
+            Some(actual) if actual.eq_ignore_ascii_case(sha256) => Ok((actual, size)),
+            Some(actual) => Err(DatastoreError::UploadMismatch(format!("{}/{} has SHA-256 {}, reported {}", s3_bucket, s3_key, actual, sha256))),
+            None => Err(DatastoreError::UploadMismatch(format!("No object at {}/{}", s3_bucket, s3_key)))
+        }
+    }
+
+    /// Record where an uploaded document is stored and its checksum, moving it from `PendingUpload` to `Uploaded`.
+    /// Fails with `NotFound` for an unknown document and with `InvalidTransition` if it was already uploaded.
+    /// With upload verification the object is checked first, failing with `UploadMismatch` if it is not as reported,
+    /// and its size is recorded. The checksum is stored as lowercase hex.
     pub async fn upload_document_completed(&self, doc_id: &str, s3_bucket: &str, s3_key: &str, sha256:&str) -> Result<()> {
         let doc_id : Vertex = doc_id.parse()?;
+        let (sha256, size) = match &self.upload_verification {
+            Some(objects) => {
+                let (sha256, size) = self.verify_upload(objects.as_ref(), &doc_id, s3_bucket, s3_key, sha256).await?;
+                (sha256, Some(size))
+            },
+            None => (sha256.to_ascii_lowercase(), None)
+        };
+        let sha256 = sha256.as_str();
         let s3_id = Vertex::DocumentS3(String::from(s3_key));
         let checksum_vertex = Vertex::ChecksumSha256(String::from(sha256));
-        
-        self.store_edge(
-            &new_edge(
-                &doc_id, 
-                &EdgeType::DocumentSelf, 
-                &doc_id, 
-                Some(VertexData::String(String::from("some document data")))
-            )
-        ).await?;
-        
-        self.store_edge(
-            &new_edge(
-                &doc_id, 
-                &EdgeType::DocumentS3, 
-                &s3_id, 
+
+        let duplicate = match self.duplicate_policy {
+            DuplicatePolicy::Allow => None,
+            _ => self.find_owned_duplicate(&doc_id, sha256).await?
+        };
+        let mut ops = Vec::new();
+        if let Some(original) = duplicate {
+            if self.duplicate_policy == DuplicatePolicy::Reject {
+                return Err(DatastoreError::Duplicate(format!("{} has the same content as {}", doc_id, original)));
+            }
+            ops.push(WriteOp::Put(new_edge(&doc_id, &EdgeType::DocumentDuplicate, &original.parse()?, None)));
+        }
+
+        ops.extend(vec![
+            WriteOp::Put(new_edge(
+                &doc_id,
+                &EdgeType::DocumentS3,
+                &s3_id,
                 Some(VertexData::S3Document(S3Document{bucket: String::from(s3_bucket), key: String::from(s3_key)}))
-            )
-        ).await?;
-        
-        self.store_edge(
-            &new_edge(
-                &doc_id, 
-                &EdgeType::DocumentChecksum, 
-                &checksum_vertex, 
+            )),
+            WriteOp::Put(new_edge(
+                &doc_id,
+                &EdgeType::DocumentChecksum,
+                &checksum_vertex,
                 None
-            )
-        ).await?;
-        
+            ))
+        ]);
+        self.transition_document(&doc_id, DocumentStatus::Uploaded, ops, |data| DocumentData { size: size.or(data.size), ..data }).await?;
         Ok(())
     }
 
-    pub async fn new_user(&self, personal_number: &str, name: &str, given_name: &str, surname: &str, email:Option<&str>, phone:Option<&str>, session_id: Option<&str>) -> Result<String> {
+    /// Documents whose uploaded content has the SHA-256 checksum `sha256`, in any letter case.
+    ///
+    /// Checksums are stored lowercase, but documents uploaded before that were stored as the client sent them.
+    /// Those are found when they are all uppercase; mixed case ones only after `normalize_document_checksum`.
+    pub async fn find_documents_by_checksum(&self, sha256: &str) -> Result<Vec<DocumentReference>> {
+        let spellings: HashSet<String> = vec![sha256.to_ascii_lowercase(), sha256.to_ascii_uppercase(), String::from(sha256)].into_iter().collect();
+        let found = try_join_all(spellings.iter().map(|spelling| {
+            let checksum_vertex = Vertex::ChecksumSha256(spelling.clone());
+            async move { self.edges_to(&checksum_vertex.to_string(), Some("doc_checksum")).await }
+        })).await?;
+        let doc_ids: HashSet<String> = found.into_iter().flatten().map(|edge| edge.vertex_a).collect();
+        let mut documents: Vec<DocumentReference> = doc_ids.into_iter().map(|doc_id| DocumentReference{doc_id}).collect();
+        documents.sort_by(|a, b| a.doc_id.cmp(&b.doc_id));
+        Ok(documents)
+    }
+
+    /// Rewrite the `doc_checksum` edge of a document stored before checksums were lowercased, so that
+    /// `find_documents_by_checksum` finds it. Returns whether the edge was rewritten.
+    pub async fn normalize_document_checksum(&self, doc_id: &str) -> Result<bool> {
+        let doc_vertex: Vertex = doc_id.parse()?;
+        let mut ops = Vec::new();
+        for edge in self.consistent_edges_from(&doc_vertex.to_string(), Some("doc_checksum")).await? {
+            if let Vertex::ChecksumSha256(sha256) = edge.vertex_b.parse()? {
+                if sha256 != sha256.to_ascii_lowercase() {
+                    ops.push(WriteOp::delete(&edge));
+                    ops.push(WriteOp::Put(new_edge(&doc_vertex, &EdgeType::DocumentChecksum, &Vertex::ChecksumSha256(sha256.to_ascii_lowercase()), None)));
+                }
+            }
+        }
+        if ops.is_empty() {
+            return Ok(false);
+        }
+        self.transact(&ops).await?;
+        Ok(true)
+    }
+
+    /// Another document with the checksum `sha256` sharing an owner with `doc_vertex`.
+    async fn find_owned_duplicate(&self, doc_vertex: &Vertex, sha256: &str) -> Result<Option<String>> {
+        let owners = |acl: &[AclEntry]| acl.iter()
+            .filter(|entry| entry.role == AclRole::Owner)
+            .map(|entry| entry.entity_id.clone())
+            .collect::<HashSet<String>>();
+        let doc_id = doc_vertex.to_string();
+        let (doc_acl, candidates) = futures::try_join!(
+            self.list_document_acl(&doc_id),
+            self.find_documents_by_checksum(sha256)
+        )?;
+        let doc_owners = owners(&doc_acl);
+        let candidates: Vec<DocumentReference> = candidates.into_iter().filter(|candidate| candidate.doc_id != doc_id).collect();
+        let candidate_acls = try_join_all(candidates.iter().map(|candidate| self.list_document_acl(&candidate.doc_id))).await?;
+        Ok(candidates.into_iter().zip(candidate_acls)
+            .find(|(_, acl)| !owners(acl).is_disjoint(&doc_owners))
+            .map(|(candidate, _)| candidate.doc_id))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new_user(&self, personal_number: &str, name: &str, given_name: &str, surname: &str, email:Option<&str>, phone:Option<&str>, _session_id: Option<&str>) -> Result<String> {
         let user_id = Vertex::User(Uuid::new_v4().to_hyphenated().to_string());
         let pno_vertex = Vertex::PersonalNumber(String::from(personal_number));    
 
-        self.store_edge(
-            &new_edge(
-                &user_id, 
-                &EdgeType::UserSelf, 
-                &user_id, 
+        let mut edges = vec![
+            new_edge(
+                &user_id,
+                &EdgeType::UserSelf,
+                &user_id,
                 Some(VertexData::UserData(
                     UserData {
                         name: Some(String::from(name)),
@@ -407,34 +982,25 @@ impl GraphDb {
                         given_name: Some(String::from(given_name))
                     }
                 ))
-            )
-        ).await?;
-
-        self.store_edge(
-            &new_edge(
-                &user_id, 
+            ),
+            new_edge(
+                &user_id,
                 &EdgeType::UserPersonalNumber,
                 &pno_vertex, 
                 None
             )
-        ).await?;        
+        ];
 
+        if let Some(email) = email {
+            let email_vertex = Vertex::Email(String::from(email));
+            edges.push(new_edge(&user_id, &EdgeType::UserEmail, &email_vertex, None));
+        }
 
-        match email {
-            Some(email) => {
-                let email_vertex = Vertex::Email(String::from(email));
-                self.store_edge(&new_edge(&user_id, &EdgeType::UserEmail, &email_vertex, None)).await?;
-            },
-            None => {}
-        };
-
-        match phone {
-            Some(phone) => {
-                let phone_vertex = Vertex::Phone(String::from(phone));
-                self.store_edge(&new_edge(&user_id, &EdgeType::UserPhone, &phone_vertex, None)).await?;
-            },
-            None => {}
-        };
+        if let Some(phone) = phone {
+            let phone_vertex = Vertex::Phone(String::from(phone));
+            edges.push(new_edge(&user_id, &EdgeType::UserPhone, &phone_vertex, None));
+        }
+        self.store_edges_atomic(&edges).await?;
         // match session_id {
         //     Some(session_id) => {
         //         let session_vertex = format!("Session-{}", session_id);
@@ -446,275 +1012,378 @@ impl GraphDb {
         Ok(user_id.to_string())
     }
 
+    /// Start a session that expires as set by `with_session_lifetime`.
     pub async fn session_new(&self) -> Result<Session> {
         let session_vertex = Vertex::Session(Uuid::new_v4().to_hyphenated().to_string());
         let now: DateTime<Utc> = Utc::now();
         let created = Some(now.to_rfc3339());
+        let data = SessionData{
+            created: created.clone(),
+            session_login_id: None,
+            login: None,
+            logout: None,
+            auth_data: None,
+            expires: self.session_lifetime.absolute.and_then(|lifetime| deadline(now, lifetime)).map(|expires| expires.to_rfc3339()),
+            idle_timeout: self.session_lifetime.idle.map(|idle| idle.as_secs()),
+            last_active: created.clone(),
+            user_id: None
+        };
+        let expires_at = data.expires_at();
 
         self.store_edge(
-            &new_edge(
-                &session_vertex, 
-                &EdgeType::SessionSelf, 
-                &session_vertex, 
-                Some(VertexData::SessionData(SessionData{created: created.clone(), session_login_id: None, login: None, logout: None, auth_data: None}))
-            )
+            &Edge {
+                expires_at: expires_at.map(|expires_at| expires_at.timestamp()),
+                ..new_edge(
+                    &session_vertex,
+                    &EdgeType::SessionSelf,
+                    &session_vertex,
+                    Some(VertexData::SessionData(data))
+                )
+            }
         ).await?;
         
         Ok(
             Session{
                 session_id: session_vertex.to_string(), 
-                created: created, 
-                session_login_id: None, 
-                login: None, 
-                logout: None, 
-                user: None, 
-                auth_data: None
+                created,
+                session_login_id: None,
+                login: None,
+                logout: None,
+                user: None,
+                auth_data: None,
+                expires_at: expires_at.map(|expires_at| expires_at.to_rfc3339())
             }
         )
     }
 
-    pub async fn session_auth(&self, session_id: &str, user_id: &str, auth_data: &str) -> Result<Session> {
+    /// Log `user_id` in to a session. A login that is still active is logged out, so a session has at most one active login.
+    /// `auth_data` is an `AuthData`, or free text as in logins stored before auth data was structured.
+    pub async fn session_auth<A: Into<AuthData>>(&self, session_id: &str, user_id: &str, auth_data: A) -> Result<Session> {
         let now: DateTime<Utc> = Utc::now();
-        let login = Some(now.to_rfc3339());
-        let session_vertex = session_id.parse()?;
+        let session_vertex: Vertex = session_id.parse()?;
+        let user_vertex: Vertex = user_id.parse()?;
         let login_vertex = Vertex::SessionLogin(Uuid::new_v4().to_hyphenated().to_string());
-        let session_login_id = Uuid::new_v4().to_hyphenated().to_string();
-
-        match self.get_user(user_id).await {
-            Some(user) => {
-                let user_vertex = user_id.parse()?;
-                let sessions = self.sessions_get(session_id).await;
-                match sessions.len() {
-                    0 => {
-                            bail!("Invalid session_id")
-                        },
-                    _ => {
-                            let session = &sessions[0];
-                            let session_data = VertexData::SessionData(SessionData{login: login.clone(), session_login_id: Some(login_vertex.to_string()), auth_data: Some(String::from(auth_data)), ..session.session_data()});
-                            self.store_edge(
-                                &new_edge(
-                                    &session_vertex, 
-                                    &&EdgeType::SessionUser, 
-                                    &user_vertex, 
-                                    None
-                                )
-                            ).await?;
-                            self.store_edge(
-                                &new_edge(
-                                    &session_vertex, 
-                                    &EdgeType::SessionLogin, 
-                                    &login_vertex, 
-                                    Some(session_data.clone())
-                                )
-                            ).await?;
-
-                            Ok(
-                                Session{
-                                    login: login, 
-                                    user: Some(user), 
-                                    ..session.clone()
-                                }
-                            )
-                        } 
-                }
-            },
-            None => bail!("Invalid user_id")
-        }
+
+        let (user, session) = futures::try_join!(
+            self.get_user(user_id),
+            self.session_edges(&session_vertex)
+        )?;
+        let session_self = session.session_self.as_ref()
+            .ok_or_else(|| DatastoreError::NotFound(String::from(session_id)))?;
+        let (session_self, unchanged) = touched_session(session_self, now)?;
+        let session_expires_at = session_self.expires_at;
+        let expires_at = edges_expire_at(&session_self);
+        let created = session.session_data().and_then(|data| data.created.clone());
+        let login = SessionData {
+            created: created.clone(),
+            session_login_id: Some(login_vertex.to_string()),
+            login: Some(now.to_rfc3339()),
+            logout: None,
+            auth_data: Some(auth_data.into()),
+            expires: None,
+            idle_timeout: None,
+            last_active: None,
+            user_id: Some(user.user_id.clone())
+        };
+
+        let superseded: Vec<&(String, SessionData)> = session.active_logins().collect();
+        let mut ops = vec![
+            WriteOp::PutIf(session_self, unchanged),
+            WriteOp::Put(Edge { expires_at, ..new_edge(
+                &session_vertex,
+                &EdgeType::SessionUser,
+                &user_vertex,
+                None
+            )}),
+            WriteOp::Put(Edge { expires_at, ..new_edge(
+                &session_vertex,
+                &EdgeType::SessionLogin,
+                &login_vertex,
+                Some(VertexData::SessionData(login.clone()))
+            )})
+        ];
+        ops.extend(session.logout_ops(&superseded, now, expires_at)?);
+        self.transact(&ops).await?;
+
+        Ok(
+            Session{
+                session_id: session.session_id.clone(),
+                session_login_id: login.session_login_id,
+                created,
+                login: login.login,
+                logout: None,
+                auth_data: login.auth_data,
+                user: Some(user),
+                expires_at: session_expires_at.and_then(|expires_at| Utc.timestamp_opt(expires_at, 0).single()).map(|expires_at| expires_at.to_rfc3339())
+            }
+        )
     }
 
+    /// Log out the active login of a session. Logging out a session that is not logged in does nothing.
     pub async fn session_logout(&self, session_id: &str) -> Result<()> {
-        let now: DateTime<Utc> = Utc::now();
-        let logout = Some(now.to_rfc3339());
-        let session_vertex = session_id.parse()?;
-
-        let sessions: Vec<Session> = self.sessions_get(session_id).await;
-        let filtered_sessions = sessions
-        .iter()
-        .filter(|item| match &item.session_login_id {
-            Some(_) => true,
-            None => false
-        });
+        let session = self.session_edges(&session_id.parse()?).await?;
+        if let SessionState::Expired = session.state(Utc::now()) {
+            return Err(DatastoreError::Expired(String::from(session_id)));
+        }
+        let logins: Vec<&(String, SessionData)> = session.active_logins().collect();
+        if !logins.is_empty() {
+            self.logout_logins(&session, &logins).await?;
+            info!("Logged out {} login(s) of session {}", logins.len(), session_id);
+        }
+        Ok(())
+    }
+
+    /// Write a logout edge for each of `logins`, bumping the version of the session so a concurrent login or logout conflicts.
+    async fn logout_logins(&self, session: &SessionEdges, logins: &[&(String, SessionData)]) -> Result<()> {
+        let session_self = session.session_self.as_ref()
+            .ok_or_else(|| DatastoreError::NotFound(session.session_id.clone()))?;
+        let (session_self, unchanged) = next_version(session_self);
+        let mut ops = vec![WriteOp::PutIf(session_self, unchanged)];
+        ops.extend(session.logout_ops(logins, Utc::now(), session.edges_expire_at())?);
+        self.transact(&ops).await
+    }
 
-        for session in &sessions {
-            match &session.session_login_id {
-                Some(session_login_id) => {
-                    println!("Parse session_login_id --->{}<---", session_login_id);
-                    match self.store_edge(&new_edge(
-                    &session_vertex, 
-                    &EdgeType::SessionLogout, 
-                    &session_login_id.parse::<Vertex>().unwrap(), 
-                    Some(VertexData::SessionData(SessionData{logout: logout.clone(), ..session.session_data()}))
-                    )).await {
-                        Ok(_) => info!("Logged out session {}", session),
-                        Err(err) => error!("Could not logout session {} {}", session, err)
+    /// The edges of a session, read with one query. Fails with `NotFound` if the session has no `session_self` edge,
+    /// as when its time to live ran out before that of its other edges.
+    async fn session_edges(&self, session_vertex: &Vertex) -> Result<SessionEdges> {
+        let edges = self.consistent_edges_from(&session_vertex.to_string(), Some("session_")).await?;
+        let mut session = SessionEdges {
+            session_id: session_vertex.to_string(),
+            session_self: None,
+            users: Vec::new(),
+            logins: Vec::new(),
+            logged_out: HashMap::new()
+        };
+        for edge in edges {
+            match (edge.edge.parse()?, &edge.data) {
+                (EdgeType::SessionSelf, _) => session.session_self = Some(edge),
+                (EdgeType::SessionUser, _) => session.users.push(edge.vertex_b),
+                (EdgeType::SessionLogin, Some(VertexData::SessionData(data))) => session.logins.push((edge.vertex_b.clone(), data.clone())),
+                (EdgeType::SessionLogout, data) => {
+                    let logout = match data {
+                        Some(VertexData::SessionData(data)) => data.logout.clone(),
+                        _ => None
                     };
+                    session.logged_out.insert(edge.vertex_b, logout);
                 },
-                None => info!("No session_login_id to logout")
+                _ => {}
             }
         }
+        if session.session_self.is_none() {
+            return Err(DatastoreError::NotFound(session_vertex.to_string()));
+        }
+        Ok(session)
+    }
 
-        Ok(()) 
-    }
-
-    pub async fn sessions_get(&self, session_id: &str) -> Vec<Session> {
-        let query_key_vertex_a: HashMap<String, AttributeValue> =
-            [(String::from(":vertex_a"), AttributeValue{        
-                    s:Some(String::from(session_id)),
-                    ..Default::default()
-                }),
-            (String::from(":userdata_prefix"), AttributeValue{        
-                    s:Some(String::from("session_")),
-                    ..Default::default()
-                })]
-            .iter().cloned().collect();
-
-        let edges_from_vertex_a : Vec<Edge> = match self.client.query(
-            QueryInput{
-                table_name: String::from("insignia-docs"),
-                key_condition_expression: Some(String::from("vertex_a = :vertex_a and begins_with(edge, :userdata_prefix)")),
-                expression_attribute_values: Some(query_key_vertex_a),
-                .. QueryInput::default()
-            }).await {
-                Ok(res) => {
-                    res.items.unwrap_or_else(|| vec![]).into_iter().map(|item| serde_dynamodb::from_hashmap(item).unwrap()).collect()
-                },
-                Err(err) =>  {
-                    vec![]
-                }
+    /// The user and login behind a session, for authenticating a request. Reads the edges of the session
+    /// and then the user. Fails with `DatastoreError::Unauthenticated` telling why the session is not valid.
+    pub async fn authenticate_session(&self, session_id: &str) -> Result<AuthenticatedSession> {
+        let unauthenticated = DatastoreError::Unauthenticated;
+        let session = match session_id.parse::<Vertex>() {
+            Ok(session_vertex @ Vertex::Session(_)) => match self.session_edges(&session_vertex).await {
+                Err(DatastoreError::NotFound(_)) => return Err(unauthenticated(AuthFailure::UnknownSession)),
+                session => session?
+            },
+            _ => return Err(unauthenticated(AuthFailure::UnknownSession))
         };
+        if session.session_data().is_none() {
+            return Err(unauthenticated(AuthFailure::UnknownSession));
+        }
+        let (session_login_id, login) = match session.state(Utc::now()) {
+            SessionState::LoggedIn(session_login_id, login) => (session_login_id, login),
+            SessionState::NotLoggedIn => return Err(unauthenticated(AuthFailure::NotLoggedIn)),
+            SessionState::LoggedOut => return Err(unauthenticated(AuthFailure::LoggedOut)),
+            SessionState::Expired => return Err(unauthenticated(AuthFailure::Expired))
+        };
+        let user_id = session.login_user(login).ok_or(unauthenticated(AuthFailure::NotLoggedIn))?;
+        let user = self.get_user(&user_id).await?;
+        Ok(session.authenticated(session_login_id, login, user))
+    }
 
-        if edges_from_vertex_a.len() == 0 {
-            return Vec::new();
+    /// Sessions in which `user_id` is logged in, found through the `session_user` edges pointing at the user.
+    pub async fn get_user_sessions(&self, user_id: &str) -> Result<Vec<AuthenticatedSession>> {
+        let user = self.get_user(user_id).await?;
+        let now = Utc::now();
+        let mut sessions = Vec::new();
+        for session in self.user_session_edges(user_id).await? {
+            if session.is_expired(now) {
+                continue;
+            }
+            let login = session.active_logins()
+                .filter(|(_, login)| session.login_user(login).as_deref() == Some(user_id))
+                .max_by(|(_, a), (_, b)| a.login.cmp(&b.login));
+            if let Some((session_login_id, login)) = login {
+                sessions.push(session.authenticated(session_login_id, login, user.clone()));
+            }
         }
+        Ok(sessions)
+    }
 
-        let mut created : Option<String> = None;
-        let mut login: Option<String> = None;
-        let mut logout: Option<String> = None;
-        let mut auth_data: Option<String> = None;
-        let mut user : Option<User> = None;
+    /// Log `user_id` out of every session, e.g. after a security incident. Returns the number of sessions logged out.
+    pub async fn logout_everywhere(&self, user_id: &str) -> Result<usize> {
+        self.logout_user_sessions(user_id, None).await
+    }
 
-        let mut session_logins: HashMap<String, VertexData> = HashMap::new();
-        let mut session_logouts: Vec<String> = Vec::new();
-        
-        for item in &edges_from_vertex_a {
-            let splitted : Vec<&str> = item.vertex_b.split("-").collect();
-            if splitted.len()>1 {
-                match splitted[0] {
-                    "User" => {
-                        user = self.get_user(&item.vertex_b).await;
-                    },
-                    "SessionLogin" => {
-                        println!("======> SessionLogin EDGE {:?} [[[vertex_a: {}]]] [[[vertex_b: {}]]] vertex_a: DATA {:?}", item, item.vertex_a, item.vertex_b, item.data);
-                        match &item.data {
-                            Some(data) => {
-                                match data {
-                                    VertexData::SessionData(session_data) => {
-                                        match &session_data.login {
-                                    
-                                            Some(login_data) => match session_logins.insert(item.vertex_b.clone(), data.clone()) {
-                                                Some(v) => error!("Unexpected duplicate value {}", v),
-                                                None => ()
-                                            },
-                                            None => ()
-                                        }
-                                        match &session_data.logout {
-                                            Some(logout_data) => {
-                                                session_logouts.push(item.vertex_b.clone())
-                                            },
-                                            None => ()
-                                        }
-                                    },
-                                    _ => ()
-                                }
-                            },
-                            None => ()
+    /// Log `user_id` out of every session but `session_id`, e.g. after a password or BankID reset.
+    /// Returns the number of sessions logged out.
+    pub async fn logout_other_sessions(&self, user_id: &str, session_id: &str) -> Result<usize> {
+        let keep: Vertex = session_id.parse()?;
+        self.logout_user_sessions(user_id, Some(&keep.to_string())).await
+    }
 
-                        }
-                        
-                    },  
-                    "Session" => {
-                        match &item.data {
-                            Some(data) => match data {
-                                VertexData::SessionData(session_data) => {
-                                    created = session_data.created.clone();
-                                    ()
-                                },
-                                _ => () 
-                            },
-                            None => ()
-                        }
-                    },
-                    _ => info!("Unknown session property {}", &item.vertex_b)
-                    
+    /// Attempts every session before failing, with the first error if any session could not be logged out.
+    async fn logout_user_sessions(&self, user_id: &str, keep: Option<&str>) -> Result<usize> {
+        let sessions: Vec<SessionEdges> = self.user_session_edges(user_id).await?.into_iter()
+            .filter(|session| Some(session.session_id.as_str()) != keep)
+            .collect();
+        let mut logged_out = 0;
+        let mut failed = None;
+        for session in sessions {
+            match self.logout_user_session(session, user_id).await {
+                Ok(true) => logged_out += 1,
+                Ok(false) => {},
+                Err(err) => {
+                    warn!("Failed to log {} out of a session: {}", user_id, err);
+                    failed.get_or_insert(err);
                 }
             }
         }
-
-        for session_logout in &session_logouts {
-            session_logins.remove(session_logout);
+        info!("Logged {} out of {} session(s)", user_id, logged_out);
+        match failed {
+            Some(err) => Err(err),
+            None => Ok(logged_out)
         }
+    }
 
-        if session_logins.len() == 0 {
-            println!("WHAT NO LOGINS");
-            return vec![
-                    Session {
-                        session_id: String::from(session_id),
-                        created: created,
-                        session_login_id: None,
-                        login: None,
-                        logout: None,
-                        auth_data: None,
-                        user: user
-                    }
-                ];
-        }
-
-        session_logins.iter().filter_map(|(session_login, vertex_data)|{
-            match vertex_data {
-                VertexData::SessionData(session_data) => Some(Session {
-                    session_id: String::from(session_id),
-                    session_login_id: Some(session_login.clone()),
-                    created: created.clone(),
-                    login: session_data.login.clone(),
-                    logout: session_data.logout.clone(),
-                    auth_data: session_data.auth_data.clone(),
-                    user: user.clone()
-                }),
-                _=> None
+    /// Log `user_id` out of one session, reading the session again when a concurrent login or logout wins.
+    /// Returns whether there was a login to log out.
+    async fn logout_user_session(&self, mut session: SessionEdges, user_id: &str) -> Result<bool> {
+        let mut attempt = 0;
+        loop {
+            let logins: Vec<&(String, SessionData)> = session.active_logins()
+                .filter(|(_, login)| session.login_user(login).as_deref() == Some(user_id))
+                .collect();
+            if logins.is_empty() {
+                return Ok(false);
             }
-        }).collect()
-    }
-
-    pub async fn get_user(&self, user_id: &str) -> Option<User> {
-        let query_key_vertex_a: HashMap<String, AttributeValue> =
-            [(String::from(":vertex_a"), AttributeValue{        
-                    s:Some(String::from(user_id)),
-                    ..Default::default()
-                }),
-            (String::from(":userdata_prefix"), AttributeValue{        
-                    s:Some(String::from("usr_")),
-                    ..Default::default()
-                })]
-            .iter().cloned().collect();
-
-        let edges_from_vertex_a : Vec<Edge> = match self.client.query(
-            QueryInput{
-                table_name: String::from("insignia-docs"),
-                key_condition_expression: Some(String::from("vertex_a = :vertex_a and begins_with(edge, :userdata_prefix)")),
-                expression_attribute_values: Some(query_key_vertex_a),
-                .. QueryInput::default()
-            }).await {
-                Ok(res) => {
-                    res.items.unwrap_or_else(|| vec![]).into_iter().map(|item| serde_dynamodb::from_hashmap(item).unwrap()).collect()
-                },
-                Err(err) =>  {
-                    error!("Error query{:?}", err);
-                    vec![]
+            attempt += 1;
+            match self.logout_logins(&session, &logins).await {
+                Err(DatastoreError::Conflict(_)) if attempt < self.retry_policy.max_attempts => {},
+                result => return result.map(|()| true)
+            }
+            session = match self.session_edges(&session.session_id.parse()?).await {
+                Err(DatastoreError::NotFound(_)) => return Ok(false),
+                session => session?
+            };
+        }
+    }
+
+    /// Every login of `user_id`, newest first, with how it was authenticated and when it was logged out.
+    /// Only logins of sessions that still exist are listed: with a `SessionLifetime`, a session and its logins are
+    /// purged by their time to live once it ends, so audits needing a longer history must record logins elsewhere.
+    pub async fn get_user_logins(&self, user_id: &str) -> Result<Vec<Login>> {
+        let mut logins = Vec::new();
+        for session in self.user_session_edges(user_id).await? {
+            for (session_login_id, login) in &session.logins {
+                if session.login_user(login).as_deref() != Some(user_id) {
+                    continue;
                 }
+                logins.push(Login {
+                    session_id: session.session_id.clone(),
+                    session_login_id: session_login_id.clone(),
+                    user_id: String::from(user_id),
+                    login: login.login.clone(),
+                    logout: login.logout.clone().or_else(|| session.logged_out.get(session_login_id).cloned().flatten()),
+                    auth_data: login.auth_data.clone()
+                });
+            }
+        }
+        logins.sort_by(|a, b| b.login.cmp(&a.login));
+        Ok(logins)
+    }
+
+    /// The edges of every session with a `session_user` edge to `user_id`, skipping sessions already gone.
+    async fn user_session_edges(&self, user_id: &str) -> Result<Vec<SessionEdges>> {
+        let user_vertex: Vertex = user_id.parse()?;
+        let session_vertices: HashSet<String> = self.edges_to(&user_vertex.to_string(), Some("session_user")).await?.into_iter()
+            .map(|edge| edge.vertex_a)
+            .collect();
+        let session_vertices = session_vertices.iter()
+            .map(|session_id| session_id.parse())
+            .collect::<Result<Vec<Vertex>>>()?;
+        let sessions = try_join_all(session_vertices.iter().map(|session_vertex| async move {
+            match self.session_edges(session_vertex).await {
+                Err(DatastoreError::NotFound(_)) => Ok(None),
+                session => session.map(Some)
+            }
+        })).await?;
+        Ok(sessions.into_iter().flatten().collect())
+    }
+
+    /// Mark a session as used now, moving its idle expiry along. Fails with `DatastoreError::Expired` if it already expired.
+    /// Usually only the `session_self` edge is written. Other edges of the session that would expire before it get
+    /// a new time to live in the same transaction, at most as many as fit next to it; later touches move the rest.
+    pub async fn touch_session(&self, session_id: &str) -> Result<()> {
+        let session_vertex: Vertex = session_id.parse()?;
+        let key = edge_key(&session_vertex, &EdgeType::SessionSelf, &session_vertex);
+        let (edge, others): (Vec<Edge>, Vec<Edge>) = self.consistent_edges_from(&session_vertex.to_string(), Some("session_")).await?.into_iter()
+            .partition(|edge| edge.edge == key);
+        let edge = edge.first().ok_or_else(|| DatastoreError::NotFound(session_vertex.to_string()))?;
+        let (session_self, unchanged) = touched_session(edge, Utc::now())?;
+        let mut expiring: Vec<Edge> = match session_self.expires_at {
+            Some(expires_at) => others.into_iter()
+                .filter(|other| !matches!(other.expires_at, Some(other) if other >= expires_at))
+                .collect(),
+            None => Vec::new()
         };
+        if expiring.is_empty() {
+            return self.store.put_edge_if(&session_self, unchanged).await;
+        }
+        expiring.sort_by_key(|other| other.expires_at);
+        let expires_at = edges_expire_at(&session_self);
+        let mut ops = vec![WriteOp::PutIf(session_self, unchanged)];
+        ops.extend(expiring.into_iter().take(TRANSACT_WRITE_LIMIT - 1).map(|other| WriteOp::Put(Edge { expires_at, ..other })));
+        self.transact(&ops).await
+    }
 
-        if edges_from_vertex_a.len() == 0 {
-            return None;
+    /// The session with its active login, if any. Fails with `DatastoreError::Expired` for an expired session.
+    /// The list holds exactly one session; it is a list for callers written when a session could have several logins.
+    pub async fn sessions_get(&self, session_id: &str) -> Result<Vec<Session>> {
+        let session = self.session_edges(&session_id.parse()?).await?;
+        let (session_login_id, login, user) = match session.state(Utc::now()) {
+            SessionState::Expired => return Err(DatastoreError::Expired(String::from(session_id))),
+            SessionState::LoggedIn(session_login_id, login) => {
+                let user = match session.login_user(login) {
+                    Some(user_id) => Some(self.get_user(&user_id).await?),
+                    None => None
+                };
+                (Some(String::from(session_login_id)), Some(login), user)
+            },
+            SessionState::NotLoggedIn | SessionState::LoggedOut => {
+                info!("No active logins for session {}", session_id);
+                (None, None, None)
+            }
+        };
+        let data = session.session_data();
+        Ok(vec![
+            Session {
+                session_id: session.session_id.clone(),
+                session_login_id,
+                created: data.and_then(|data| data.created.clone()),
+                login: login.and_then(|login| login.login.clone()),
+                logout: None,
+                auth_data: login.and_then(|login| login.auth_data.clone()),
+                user,
+                expires_at: data.and_then(|data| data.expires_at()).map(|expires_at| expires_at.to_rfc3339())
+            }
+        ])
+    }
+
+    pub async fn get_user(&self, user_id: &str) -> Result<User> {
+        let edges_from_vertex_a = self.edges_from(user_id, Some("usr_")).await?;
+
+        if edges_from_vertex_a.is_empty() {
+            return Err(DatastoreError::NotFound(String::from(user_id)));
         }
 
         let mut name : Option<String> = None;
@@ -725,7 +1394,7 @@ impl GraphDb {
         let mut phone: Option<String> = None;
 
         for item in &edges_from_vertex_a {
-            let splitted : Vec<&str> = item.vertex_b.split("-").collect();
+            let splitted : Vec<&str> = item.vertex_b.split('-').collect();
             if splitted.len()>1 {
                 match splitted[0] {
                     "Email" => {
@@ -738,17 +1407,10 @@ impl GraphDb {
                         phone = Some(splitted[1..].join("-"));
                     },
                     "User" => {
-                        match &item.data {
-                            Some(data) => match data {
-                                VertexData::UserData(user_data) => {
-                                    name = user_data.name.clone();
-                                    given_name = user_data.given_name.clone();
-                                    surname = user_data.surname.clone();
-                                    ()
-                                },
-                                _ => () 
-                            },
-                            None => ()
+                        if let Some(VertexData::UserData(user_data)) = &item.data {
+                            name = user_data.name.clone();
+                            given_name = user_data.given_name.clone();
+                            surname = user_data.surname.clone();
                         }
                     },
                     _ => info!("Unknown user property {}", &item.vertex_b)
@@ -757,95 +1419,510 @@ impl GraphDb {
             }
         }
 
-        Some(User{
+        Ok(User{
             user_id: String::from(user_id),
-            name: name,
-            given_name: given_name,
-            surname: surname,
-            personal_number: personal_number,
-            email: email,
-            phone: phone
+            name,
+            given_name,
+            surname,
+            personal_number,
+            email,
+            phone
+        })
+    }
+
+    pub async fn get_user_documents(&self, user_id: &str) -> Result<Vec<DocumentReference>> {
+        let edges_from_vertex_a = self.edges_from(user_id, Some("doc_acl_")).await?;
+
+        Ok(edges_from_vertex_a.iter().map(|item|DocumentReference{doc_id: item.vertex_b.clone()}).collect::<Vec<DocumentReference>>())
+    }
+
+
+
+    pub async fn get_user_documents_page(&self, user_id: &str, limit: i64, next_token: Option<&str>) -> Result<Page<DocumentReference>> {
+        let page = self.get_edges_page(user_id, Some("doc_acl_"), limit, next_token).await?;
+        Ok(Page {
+            items: page.items.iter().map(|item|DocumentReference{doc_id: item.vertex_b.clone()}).collect(),
+            next_token: page.next_token
         })
     }
 
-    pub async fn get_user_documents(&self, user_id: &str) -> Vec<DocumentReference> {
-        let query_key_vertex_a: HashMap<String, AttributeValue> =
-            [(String::from(":vertex_a"), AttributeValue{        
-                    s:Some(String::from(user_id)),
-                    ..Default::default()
-                }),
-            (String::from(":userdocs"), AttributeValue{        
-                    s:Some(String::from("doc_acl_")), 
-                    ..Default::default()
-                })]
-            .iter().cloned().collect();
-
-        let edges_from_vertex_a : Vec<Edge> = match self.client.query(
-            QueryInput{
-                table_name: String::from("insignia-docs"),
-                key_condition_expression: Some(String::from("vertex_a = :vertex_a and begins_with(edge, :userdocs)")),
-                expression_attribute_values: Some(query_key_vertex_a),
-                .. QueryInput::default()
-            }).await {
-                Ok(res) => {
-                    res.items.unwrap_or_else(|| vec![]).into_iter().map(|item| serde_dynamodb::from_hashmap(item).unwrap()).collect()
+    pub async fn get_users_by_personal_number(&self, personal_number: &str) -> Result<Vec<User>> {
+        let pno_vertex = Vertex::PersonalNumber(String::from(personal_number));
+        let edges_from_vertex_b = self.edges_to(&pno_vertex.to_string(), None).await?;
+        let user_futures = edges_from_vertex_b.iter().map(
+            |itm| self.get_user(&itm.vertex_a)
+        );
+
+        try_join_all(user_futures).await
+    }
+
+    /// The user or organisation behind a vertex id.
+    pub async fn get_legal_entity(&self, vertex_id: &str) -> Result<LegalEntity> {
+        match vertex_id.parse()? {
+            Vertex::User(_) => Ok(LegalEntity::User(self.get_user(vertex_id).await?)),
+            Vertex::Organisation(_) => Ok(LegalEntity::Org(self.get_organisation(vertex_id).await?)),
+            _ => Err(DatastoreError::InvalidVertex(String::from(vertex_id)))
+        }
+    }
+
+    /// A document with its owners, readers, S3 location, checksum, sign requests and signatures.
+    ///
+    /// Reads the `doc_` edges of the document and the ACL edges pointing at it, then the users and
+    /// organisations they lead to.
+    pub async fn get_document(&self, doc_id: &str) -> Result<Document> {
+        let doc_vertex: Vertex = doc_id.parse()?;
+        let doc_id = doc_vertex.to_string();
+        let (doc_edges, acl_edges) = futures::try_join!(
+            self.edges_from(&doc_id, Some("doc_")),
+            self.edges_to(&doc_id, Some("doc_acl_"))
+        )?;
+        if doc_edges.is_empty() && acl_edges.is_empty() {
+            return Err(DatastoreError::NotFound(doc_id));
+        }
+
+        let mut document = Document {
+            doc_id: doc_id.clone(),
+            metadata: None,
+            owners: Vec::new(),
+            readers: Vec::new(),
+            s3: None,
+            checksum: None,
+            signatures: Vec::new(),
+            signature_reqs: Vec::new(),
+            duplicate_of: None
+        };
+        let mut owners = Vec::new();
+        let mut readers = Vec::new();
+        for edge in &acl_edges {
+            match edge.edge.parse()? {
+                EdgeType::DocumentOwner => owners.push(edge.vertex_a.as_str()),
+                EdgeType::DocumentReader => readers.push(edge.vertex_a.as_str()),
+                _ => info!("Unknown document acl edge {}", edge.edge)
+            }
+        }
+        let mut signature_reqs = Vec::new();
+        let mut signatures = Vec::new();
+        for edge in &doc_edges {
+            match edge.edge.parse()? {
+                EdgeType::DocumentSelf => if let Some(VertexData::DocumentData(data)) = &edge.data {
+                    document.metadata = Some(data.clone());
                 },
-                Err(err) =>  {
-                    error!("Error query{:?}", err);
-                    vec![]
+                EdgeType::DocumentS3 => if let Some(VertexData::S3Document(s3)) = &edge.data {
+                    document.s3 = Some(s3.clone());
+                },
+                EdgeType::DocumentChecksum => if let Vertex::ChecksumSha256(sha256) = edge.vertex_b.parse()? {
+                    document.checksum = Some(sha256);
+                },
+                EdgeType::DocumentSignRequest if SignRequest::from_edge(edge).status == SignRequestStatus::Pending => {
+                    signature_reqs.push(edge.vertex_b.as_str());
+                },
+                EdgeType::DocumentSignature => signatures.push(edge.vertex_b.as_str()),
+                EdgeType::DocumentDuplicate => document.duplicate_of = Some(edge.vertex_b.clone()),
+                _ => {}
+            }
+        }
+        let (owners, readers, signature_reqs, signatures) = futures::try_join!(
+            try_join_all(owners.iter().map(|id| self.get_legal_entity(id))),
+            try_join_all(readers.iter().map(|id| self.get_legal_entity(id))),
+            try_join_all(signature_reqs.iter().map(|id| self.get_legal_entity(id))),
+            try_join_all(signatures.iter().map(|id| self.get_legal_entity(id)))
+        )?;
+        Ok(Document {
+            owners,
+            readers,
+            signature_reqs,
+            signatures,
+            ..document
+        })
+    }
+
+    /// Every user and organisation with access to a document.
+    pub async fn list_document_acl(&self, doc_id: &str) -> Result<Vec<AclEntry>> {
+        let doc_vertex: Vertex = doc_id.parse()?;
+        let mut acl = Vec::new();
+        for edge in self.edges_to(&doc_vertex.to_string(), Some("doc_acl_")).await? {
+            let role = match edge.edge.parse()? {
+                EdgeType::DocumentOwner => AclRole::Owner,
+                EdgeType::DocumentReader => AclRole::Reader,
+                _ => continue
+            };
+            acl.push(AclEntry { entity_id: edge.vertex_a, role });
+        }
+        Ok(acl)
+    }
+
+    /// Let a user, or every member of an organisation, read a document. `user_id` must be allowed to manage access to it.
+    pub async fn grant_reader(&self, doc_id: &str, entity_id: &str, user_id: &str) -> Result<()> {
+        let doc_vertex: Vertex = doc_id.parse()?;
+        self.authorize(user_id, doc_id, DocumentAction::ManageAccess).await?;
+        self.get_legal_entity(entity_id).await?;
+        self.store_edge(&new_edge(&entity_id.parse()?, &EdgeType::DocumentReader, &doc_vertex, None)).await
+    }
+
+    /// Remove every ACL edge from `entity_id` to a document. `user_id` must be allowed to manage access to it.
+    /// The last owner cannot be removed.
+    pub async fn revoke_access(&self, doc_id: &str, entity_id: &str, user_id: &str) -> Result<()> {
+        self.authorize(user_id, doc_id, DocumentAction::ManageAccess).await?;
+        let ops = self.revoke_access_ops(&doc_id.parse()?, &entity_id.parse()?).await?;
+        if !ops.is_empty() {
+            self.transact(&ops).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes of the ACL edges from `entity_vertex` to a document, with a bump of the version of the document so
+    /// a concurrent revoke conflicts instead of both removing an owner. Empty if the entity has no access.
+    async fn revoke_access_ops(&self, doc_vertex: &Vertex, entity_vertex: &Vertex) -> Result<Vec<WriteOp>> {
+        let doc_self = self.document_self(doc_vertex).await?;
+        let acl = self.list_document_acl(&doc_vertex.to_string()).await?;
+        let owners = acl.iter().filter(|entry| entry.role == AclRole::Owner).count();
+        let revoked: Vec<&AclEntry> = acl.iter().filter(|entry| entry.entity_id == entity_vertex.to_string()).collect();
+        if owners > 0 && revoked.iter().filter(|entry| entry.role == AclRole::Owner).count() == owners {
+            return Err(DatastoreError::Conflict(format!("{} is the last owner of {}", entity_vertex, doc_vertex)));
+        }
+        if revoked.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (doc_self, unchanged) = next_version(&doc_self);
+        let mut ops = vec![WriteOp::PutIf(doc_self, unchanged)];
+        ops.extend(revoked.iter().map(|entry| WriteOp::delete(&new_edge(entity_vertex, &entry.role.edge_type(), doc_vertex, None))));
+        Ok(ops)
+    }
+
+    /// Succeed if `user_id` may perform `action` on a document, through its own ACL edges or those of an
+    /// organisation it is a member of. Fails with `DatastoreError::Unauthorized` otherwise.
+    pub async fn authorize(&self, user_id: &str, doc_id: &str, action: DocumentAction) -> Result<()> {
+        let user_vertex: Vertex = user_id.parse()?;
+        let user_id = user_vertex.to_string();
+        let (acl, memberships) = futures::try_join!(
+            self.list_document_acl(doc_id),
+            self.edges_to(&user_id, Some("org_member"))
+        )?;
+
+        let allowed = acl.iter()
+            .filter(|entry| entry.entity_id == user_id || memberships.iter().any(|membership| membership.vertex_a == entry.entity_id))
+            .any(|entry| entry.role.allows(action));
+        if allowed {
+            Ok(())
+        } else {
+            Err(DatastoreError::Unauthorized(format!("{} may not {} {}", user_id, action, doc_id)))
+        }
+    }
+
+    /// The `doc_signreq` edge from `doc_vertex` to `signer_vertex`.
+    async fn sign_request_edge(&self, doc_vertex: &Vertex, signer_vertex: &Vertex) -> Result<Edge> {
+        let key = edge_key(doc_vertex, &EdgeType::DocumentSignRequest, signer_vertex);
+        self.get_edge(&doc_vertex.to_string(), &key).await?
+            .ok_or_else(|| DatastoreError::NotFound(format!("Sign request for {} on {}", signer_vertex, doc_vertex)))
+    }
+
+    /// Ask a user or organisation to sign a document. A signer can have one pending request per document;
+    /// a signed, declined or cancelled request is replaced by the new one. `requested_by` must be a user
+    /// allowed to manage access to the document.
+    pub async fn request_signature(&self, doc_id: &str, signer_id: &str, requested_by: &str, due: Option<DateTime<Utc>>, message: Option<&str>) -> Result<SignRequest> {
+        let doc_vertex: Vertex = doc_id.parse()?;
+        let signer_vertex: Vertex = signer_id.parse()?;
+        let requested_by = match requested_by.parse()? {
+            requester @ Vertex::User(_) => requester.to_string(),
+            _ => return Err(DatastoreError::InvalidVertex(String::from(requested_by)))
+        };
+        self.authorize(&requested_by, doc_id, DocumentAction::ManageAccess).await?;
+        self.get_legal_entity(signer_id).await?;
+        if let Some(VertexData::DocumentData(data)) = self.document_self(&doc_vertex).await?.data {
+            if data.status == DocumentStatus::PendingUpload || data.status == DocumentStatus::Archived {
+                return Err(DatastoreError::InvalidTransition(format!("Cannot request signatures on {} while it is {}", doc_id, data.status)));
+            }
+        }
+
+        let data = SignRequestData {
+            status: SignRequestStatus::Pending,
+            requested: Utc::now().to_rfc3339(),
+            requested_by,
+            due: due.map(|due| due.to_rfc3339()),
+            message: message.map(String::from),
+            resolved: None,
+            resolved_by: None
+        };
+        let edge = new_edge(&doc_vertex, &EdgeType::DocumentSignRequest, &signer_vertex, Some(VertexData::SignRequestData(data)));
+        let mut ops = Vec::new();
+        let (stored, condition) = match self.sign_request_edge(&doc_vertex, &signer_vertex).await {
+            Ok(previous) => {
+                match SignRequest::from_edge(&previous).status {
+                    SignRequestStatus::Pending => return Err(DatastoreError::Conflict(format!("{} already has a pending sign request on {}", signer_id, doc_id))),
+                    SignRequestStatus::Signed => ops.push(WriteOp::delete(&new_edge(&doc_vertex, &EdgeType::DocumentSignature, &signer_vertex, None))),
+                    _ => {}
                 }
+                next_version(&Edge { version: previous.version, ..edge })
+            },
+            Err(DatastoreError::NotFound(_)) => (Edge { version: Some(1), ..edge }, WriteCondition::NotExists),
+            Err(err) => return Err(err)
         };
+        self.write_sign_request(&doc_vertex, stored.clone(), condition, ops).await?;
+        Ok(SignRequest::from_edge(&stored))
+    }
+
+    pub async fn get_document_sign_requests(&self, doc_id: &str) -> Result<Vec<SignRequest>> {
+        Ok(self.edges_from(doc_id, Some("doc_signreq")).await?.iter().map(SignRequest::from_edge).collect())
+    }
 
-        if edges_from_vertex_a.len() == 0 {
-            return vec![];
+    /// Pending sign requests addressed to `signer_id`, a user or an organisation, found through the `vertex_b` index.
+    pub async fn get_pending_sign_requests(&self, signer_id: &str) -> Result<Vec<SignRequest>> {
+        Ok(self.edges_to(signer_id, Some("doc_signreq")).await?.iter()
+            .map(SignRequest::from_edge)
+            .filter(|request| request.status == SignRequestStatus::Pending)
+            .collect())
+    }
+
+    /// Fail with `Unauthorized` unless `user_id` is the signer, or a member of the signing organisation.
+    async fn check_signer(&self, signer_vertex: &Vertex, user_id: &str) -> Result<()> {
+        let allowed = match signer_vertex {
+            Vertex::User(_) => signer_vertex.to_string() == user_id,
+            Vertex::Organisation(_) => self.get_organisation_members(&signer_vertex.to_string()).await?
+                .iter().any(|member| member.user_id == user_id),
+            _ => false
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(DatastoreError::Unauthorized(format!("{} cannot sign for {}", user_id, signer_vertex)))
+        }
+    }
+
+    /// Move a pending request to `status`, along with any extra writes, unless someone else resolved it first.
+    async fn resolve_sign_request(&self, doc_vertex: &Vertex, signer_vertex: &Vertex, user_id: &str, status: SignRequestStatus, extra: Vec<WriteOp>) -> Result<SignRequest> {
+        let edge = self.sign_request_edge(doc_vertex, signer_vertex).await?;
+        let mut request = SignRequest::from_edge(&edge);
+        if request.status != SignRequestStatus::Pending {
+            return Err(DatastoreError::Conflict(format!("Sign request for {} on {} is {}", signer_vertex, doc_vertex, request.status)));
         }
+        request.status = status;
+        request.resolved = Some(Utc::now().to_rfc3339());
+        request.resolved_by = Some(String::from(user_id));
+
+        let (resolved, unchanged) = next_version(&Edge {
+            data: Some(VertexData::SignRequestData(request.sign_request_data())),
+            ..edge
+        });
+        self.write_sign_request(doc_vertex, resolved, unchanged, extra).await?;
+        Ok(request)
+    }
 
-        edges_from_vertex_a.iter().map(|item|DocumentReference{doc_id: item.vertex_b.clone()}).collect::<Vec<DocumentReference>>()
+    /// The current `SHA256-` checksum vertex of a document.
+    async fn document_checksum(&self, doc_vertex: &Vertex) -> Result<Option<String>> {
+        let edges = self.edges_from(&doc_vertex.to_string(), Some("doc_checksum")).await?;
+        Ok(edges.into_iter().next().map(|edge| edge.vertex_b))
     }
 
+    /// Record that `user_id` signed the pending request to `signer_id`, either as that user or for that organisation.
+    ///
+    /// The signature is bound to the checksum the document has now, so later changes to the content can be detected.
+    pub async fn sign_document(&self, doc_id: &str, signer_id: &str, user_id: &str, payload: &str) -> Result<SignRequest> {
+        let doc_vertex: Vertex = doc_id.parse()?;
+        let signer_vertex: Vertex = signer_id.parse()?;
+        self.check_signer(&signer_vertex, user_id).await?;
+        let checksum = self.document_checksum(&doc_vertex).await?
+            .ok_or_else(|| DatastoreError::NotFound(format!("Checksum of {}", doc_vertex)))?;
+        let signature = new_edge(&doc_vertex, &EdgeType::DocumentSignature, &signer_vertex, Some(VertexData::SignatureData(SignatureData {
+            checksum,
+            payload: String::from(payload),
+            signed: Utc::now().to_rfc3339(),
+            signed_by: String::from(user_id)
+        })));
+        self.resolve_sign_request(&doc_vertex, &signer_vertex, user_id, SignRequestStatus::Signed, vec![WriteOp::Put(signature)]).await
+    }
 
+    /// The signer, or a member of the signing organisation, refuses to sign.
+    pub async fn decline_sign_request(&self, doc_id: &str, signer_id: &str, user_id: &str) -> Result<SignRequest> {
+        let doc_vertex: Vertex = doc_id.parse()?;
+        let signer_vertex: Vertex = signer_id.parse()?;
+        self.check_signer(&signer_vertex, user_id).await?;
+        self.resolve_sign_request(&doc_vertex, &signer_vertex, user_id, SignRequestStatus::Declined, Vec::new()).await
+    }
 
-    pub async fn get_users_by_personal_number(&self, personal_number: &str) -> Vec<User> {
-        let query_key_vertex_b: HashMap<String, AttributeValue> =
-            [(String::from(":vertex_b"), AttributeValue{        
-                    s:Some(format!("PersonalNumber-{}", personal_number)),
-                    ..Default::default()
-                })]
-            .iter().cloned().collect();
+    /// Withdraw a pending request. Only the user who requested the signature, or one who may manage access
+    /// to the document, can do so.
+    pub async fn cancel_sign_request(&self, doc_id: &str, signer_id: &str, user_id: &str) -> Result<SignRequest> {
+        let doc_vertex: Vertex = doc_id.parse()?;
+        let signer_vertex: Vertex = signer_id.parse()?;
+        let request = SignRequest::from_edge(&self.sign_request_edge(&doc_vertex, &signer_vertex).await?);
+        if request.requested_by != user_id {
+            self.authorize(user_id, doc_id, DocumentAction::ManageAccess).await?;
+        }
+        self.resolve_sign_request(&doc_vertex, &signer_vertex, user_id, SignRequestStatus::Cancelled, Vec::new()).await
+    }
 
-        let edges_from_vertex_b : Vec<Edge> = match self.client.query(
-            QueryInput{
-                table_name: String::from("insignia-docs"),
-                key_condition_expression: Some(String::from("vertex_b = :vertex_b")),
-                index_name: Some(String::from("index-vertex_b_edges")),
-                expression_attribute_values: Some(query_key_vertex_b),
-                .. QueryInput::default()
-            }).await {
-                Ok(res) => {
-                    res.items.unwrap_or_else(|| vec![]).into_iter().map(|item| serde_dynamodb::from_hashmap(item).unwrap()).collect()
-                },
-                Err(err) =>  {
-                    error!("Error query{:?}", err);
-                    vec![]
+    /// Check every signature of a document against its current `doc_checksum` edge.
+    pub async fn verify_signatures(&self, doc_id: &str) -> Result<SignatureVerification> {
+        let doc_vertex: Vertex = doc_id.parse()?;
+        let checksum = self.document_checksum(&doc_vertex).await?;
+        let signatures = self.edges_from(&doc_vertex.to_string(), Some("doc_signature")).await?.into_iter()
+            .map(|edge| {
+                let signature = match edge.data {
+                    Some(VertexData::SignatureData(data)) => Some(data),
+                    _ => None
+                };
+                SignatureCheck {
+                    matches_checksum: match (&signature, &checksum) {
+                        (Some(data), Some(checksum)) => data.checksum.eq_ignore_ascii_case(checksum),
+                        (signature, checksum) => signature.is_none() && checksum.is_none()
+                    },
+                    signer_id: edge.vertex_b,
+                    signature
                 }
+            })
+            .collect();
+        Ok(SignatureVerification {
+            doc_id: doc_vertex.to_string(),
+            checksum,
+            signatures
+        })
+    }
+
+    /// True when the document has sign requests that are not cancelled, and all of them are signed.
+    pub async fn is_fully_signed(&self, doc_id: &str) -> Result<bool> {
+        let requests: Vec<SignRequest> = self.get_document_sign_requests(doc_id).await?.into_iter()
+            .filter(|request| request.status != SignRequestStatus::Cancelled)
+            .collect();
+        Ok(!requests.is_empty() && requests.iter().all(|request| request.status == SignRequestStatus::Signed))
+    }
+
+    /// The `org_self` edge, which carries the organisation data.
+    async fn organisation_self(&self, org_vertex: &Vertex) -> Result<Edge> {
+        let key = edge_key(org_vertex, &EdgeType::OrganisationSelf, org_vertex);
+        self.get_edge(&org_vertex.to_string(), &key).await?
+            .ok_or_else(|| DatastoreError::NotFound(org_vertex.to_string()))
+    }
+
+    /// Claim on an organisation number. Written create-only next to the `org_no` edge, so two
+    /// organisations cannot end up with the same number.
+    fn org_no_claim(org_no: &str) -> Edge {
+        let org_no_vertex = Vertex::OrgNo(String::from(org_no));
+        new_edge(&org_no_vertex, &EdgeType::OrgNoSelf, &org_no_vertex, None)
+    }
+
+    /// Create an organisation. Fails with `DatastoreError::Conflict` if `org_no` is taken.
+    pub async fn new_organisation(&self, org_no: &str, name: &str) -> Result<Organisation> {
+        let org_vertex = Vertex::Organisation(Uuid::new_v4().to_hyphenated().to_string());
+        let data = OrganisationData { org_no: String::from(org_no), name: String::from(name) };
+        let org_self = Edge {
+            version: Some(1),
+            ..new_edge(&org_vertex, &EdgeType::OrganisationSelf, &org_vertex, Some(VertexData::OrganisationData(data)))
         };
-        let userFutures = edges_from_vertex_b.iter().map(
-            |itm| self.get_user(&itm.vertex_a)
-        );
-        let users: Vec<Option<User>> = join_all(userFutures).await; 
+        self.transact(&[
+            WriteOp::PutIf(org_self, WriteCondition::NotExists),
+            WriteOp::PutIf(Self::org_no_claim(org_no), WriteCondition::NotExists),
+            WriteOp::Put(new_edge(&org_vertex, &EdgeType::OrganisationNo, &Vertex::OrgNo(String::from(org_no)), None))
+        ]).await.map_err(|err| match err {
+            DatastoreError::Conflict(_) => DatastoreError::Conflict(format!("Organisation number {} is taken", org_no)),
+            err => err
+        })?;
+
+        Ok(Organisation {
+            id: org_vertex.to_string(),
+            org_no: String::from(org_no),
+            name: String::from(name)
+        })
+    }
 
-        users.iter().filter(
-            |itm| match itm { 
-                Some(_)=> true, 
-                None=> false
-            }
-        ).map(
-            |itm| itm.clone().unwrap()
-        ).collect::<Vec<User>>()
+    pub async fn get_organisation(&self, org_id: &str) -> Result<Organisation> {
+        let org_vertex: Vertex = org_id.parse()?;
+        Self::organisation(&self.organisation_self(&org_vertex).await?)
+    }
+
+    /// The organisation an `org_self` edge describes.
+    fn organisation(org_self: &Edge) -> Result<Organisation> {
+        match &org_self.data {
+            Some(VertexData::OrganisationData(data)) => Ok(Organisation {
+                id: org_self.vertex_a.clone(),
+                org_no: data.org_no.clone(),
+                name: data.name.clone()
+            }),
+            _ => Err(DatastoreError::Serialization(format!("{} has no organisation data", org_self.vertex_a)))
+        }
+    }
+
+    /// Write the name and organisation number of `organisation`, moving the `org_no` edge and the claim on the
+    /// number if it changed. Fails with `DatastoreError::Conflict` if the new number is taken.
+    pub async fn update_organisation(&self, organisation: &Organisation) -> Result<Organisation> {
+        let org_vertex: Vertex = organisation.id.parse()?;
+        let stored = self.organisation_self(&org_vertex).await?;
+        let current = Self::organisation(&stored)?;
+        let data = OrganisationData { org_no: organisation.org_no.clone(), name: organisation.name.clone() };
+        let (org_self, unchanged) = next_version(&Edge {
+            data: Some(VertexData::OrganisationData(data)),
+            ..stored
+        });
+
+        let mut ops = vec![WriteOp::PutIf(org_self, unchanged)];
+        if current.org_no != organisation.org_no {
+            ops.push(WriteOp::delete(&Self::org_no_claim(&current.org_no)));
+            ops.push(WriteOp::PutIf(Self::org_no_claim(&organisation.org_no), WriteCondition::NotExists));
+            ops.push(WriteOp::delete(&new_edge(&org_vertex, &EdgeType::OrganisationNo, &Vertex::OrgNo(current.org_no), None)));
+            ops.push(WriteOp::Put(new_edge(&org_vertex, &EdgeType::OrganisationNo, &Vertex::OrgNo(organisation.org_no.clone()), None)));
+        }
+        self.transact(&ops).await?;
+        Ok(organisation.clone())
+    }
+
+    pub async fn get_organisation_by_org_no(&self, org_no: &str) -> Result<Organisation> {
+        let org_no_vertex = Vertex::OrgNo(String::from(org_no));
+        match self.edges_to(&org_no_vertex.to_string(), Some("org_no")).await?.first() {
+            Some(edge) => self.get_organisation(&edge.vertex_a).await,
+            None => Err(DatastoreError::NotFound(org_no_vertex.to_string()))
+        }
+    }
+
+    /// Add a user to an organisation, or change the role of a member.
+    pub async fn organisation_add_user(&self, org_id: &str, user_id: &str, role: &str) -> Result<()> {
+        let org_vertex: Vertex = org_id.parse()?;
+        let user_vertex: Vertex = user_id.parse()?;
+        self.get_organisation(org_id).await?;
+        self.get_user(user_id).await?;
+        self.store_edge(&new_edge(
+            &org_vertex,
+            &EdgeType::OrganisationMember,
+            &user_vertex,
+            Some(VertexData::MembershipData(MembershipData { role: String::from(role) }))
+        )).await
+    }
+
+    pub async fn organisation_remove_user(&self, org_id: &str, user_id: &str) -> Result<()> {
+        let org_vertex: Vertex = org_id.parse()?;
+        let user_vertex: Vertex = user_id.parse()?;
+        self.store.delete_edge(&org_vertex.to_string(), &edge_key(&org_vertex, &EdgeType::OrganisationMember, &user_vertex)).await
+    }
+
+    pub async fn get_organisation_members(&self, org_id: &str) -> Result<Vec<Member>> {
+        Ok(self.edges_from(org_id, Some("org_member")).await?.into_iter()
+            .map(|edge| Member {
+                role: membership_role(&edge),
+                user_id: edge.vertex_b
+            })
+            .collect())
+    }
+
+    /// The organisations `user_id` is a member of, found through the `vertex_b` index.
+    pub async fn get_user_organisations(&self, user_id: &str) -> Result<Vec<Membership>> {
+        let edges = self.edges_to(user_id, Some("org_member")).await?;
+        let org_futures = edges.iter().map(|edge| async move {
+            Ok(Membership {
+                organisation: self.get_organisation(&edge.vertex_a).await?,
+                role: membership_role(edge)
+            })
+        });
+        try_join_all(org_futures).await
     }
 
 }
 
+fn membership_role(edge: &Edge) -> String {
+    match &edge.data {
+        Some(VertexData::MembershipData(data)) => data.role.clone(),
+        _ => String::new()
+    }
+}
+
 
 /*
 user-nnnn   user-nnnn       givenName, surname
@@ -885,4 +1962,910 @@ mod tests {
 
             Ok(())
         }
+
+    fn memory_db() -> GraphDb<MemoryStore> {
+        GraphDb::with_store(MemoryStore::new())
+    }
+
+    async fn session_self(db: &GraphDb<MemoryStore>, session_vertex: &Vertex) -> Result<Edge> {
+        let key = edge_key(session_vertex, &EdgeType::SessionSelf, session_vertex);
+        Ok(db.get_edge(&session_vertex.to_string(), &key).await?.unwrap())
+    }
+
+    fn unavailable_store() -> MemoryStore {
+        MemoryStore::new().with_fault(|_| Some(DatastoreError::Backend(String::from("unavailable"))))
+    }
+
+    async fn tolvan(db: &GraphDb<MemoryStore>) -> Result<String> {
+        db.new_user("191212121212", "Tolvan Tolvansson", "Tolvan", "Tolvansson", Some("tolvan.tolvansson@motrice.se"), Some("+46733414983"), None).await
+    }
+
+    #[tokio::test]
+    async fn new_user_round_trips() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+
+        let user = db.get_user(&user_id).await?;
+        assert_eq!(user.given_name.as_deref(), Some("Tolvan"));
+        assert_eq!(user.personal_number.as_deref(), Some("191212121212"));
+        assert_eq!(user.email.as_deref(), Some("tolvan.tolvansson@motrice.se"));
+        assert_eq!(user.phone.as_deref(), Some("+46733414983"));
+
+        let users = db.get_users_by_personal_number("191212121212").await?;
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].user_id, user_id);
+        assert!(db.get_users_by_personal_number("191111111111").await?.is_empty());
+        assert_eq!(db.get_user("User-unknown").await.unwrap_err(), DatastoreError::NotFound(String::from("User-unknown")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn session_login_and_logout() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+        let session = db.session_new().await?;
+
+        let sessions = db.sessions_get(&session.session_id).await?;
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].login.is_none());
+
+        db.session_auth(&session.session_id, &user_id, "logged in from ip 123.456.7.8").await?;
+        let sessions = db.sessions_get(&session.session_id).await?;
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].login.is_some());
+        assert_eq!(sessions[0].user.as_ref().map(|u| u.user_id.as_str()), Some(user_id.as_str()));
+
+        db.session_logout(&session.session_id).await?;
+        let sessions = db.sessions_get(&session.session_id).await?;
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].session_login_id.is_none());
+
+        assert!(matches!(db.session_auth(&session.session_id, "User-unknown", "").await, Err(DatastoreError::NotFound(_))));
+        assert!(matches!(db.sessions_get("Session-unknown").await, Err(DatastoreError::NotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn session_vertices_round_trip() -> Result<()> {
+        for id in &["Session-1", "SessionLogin-a-b", "SessionLogout-a-b"] {
+            assert_eq!(&id.parse::<Vertex>()?.to_string(), id);
+        }
+        assert!(matches!("SessionLogout-1".parse::<Vertex>()?, Vertex::SessionLogout(id) if id == "1"));
+        Ok(())
+    }
+
+    enum SessionStep {
+        Login(usize),
+        Logout
+    }
+
+    #[tokio::test]
+    async fn session_state_round_trips_through_storage() -> Result<()> {
+        let db = memory_db();
+        let users = [
+            tolvan(&db).await?,
+            db.new_user("191111111111", "Elvan Elvansson", "Elvan", "Elvansson", None, None, None).await?
+        ];
+
+        // Each step with the user expected to be logged in afterwards, or why authentication fails.
+        let matrix: Vec<(Vec<SessionStep>, std::result::Result<usize, AuthFailure>)> = vec![
+            (vec![], Err(AuthFailure::NotLoggedIn)),
+            (vec![SessionStep::Logout], Err(AuthFailure::NotLoggedIn)),
+            (vec![SessionStep::Login(0)], Ok(0)),
+            (vec![SessionStep::Login(0), SessionStep::Logout], Err(AuthFailure::LoggedOut)),
+            (vec![SessionStep::Login(0), SessionStep::Logout, SessionStep::Logout], Err(AuthFailure::LoggedOut)),
+            (vec![SessionStep::Login(0), SessionStep::Logout, SessionStep::Login(0)], Ok(0)),
+            (vec![SessionStep::Login(0), SessionStep::Login(0)], Ok(0)),
+            (vec![SessionStep::Login(0), SessionStep::Login(1)], Ok(1)),
+            (vec![SessionStep::Login(0), SessionStep::Login(1), SessionStep::Logout], Err(AuthFailure::LoggedOut)),
+            (vec![SessionStep::Login(0), SessionStep::Logout, SessionStep::Login(1), SessionStep::Logout, SessionStep::Login(0)], Ok(0))
+        ];
+
+        for (steps, expected) in matrix {
+            let session_id = db.session_new().await?.session_id;
+            let mut last_login = None;
+            for step in &steps {
+                match step {
+                    SessionStep::Login(user) => last_login = db.session_auth(&session_id, &users[*user], "ip 127.0.0.1").await?.session_login_id,
+                    SessionStep::Logout => db.session_logout(&session_id).await?
+                }
+            }
+
+            let sessions = db.sessions_get(&session_id).await?;
+            assert_eq!(sessions.len(), 1);
+            let logins = steps.iter().filter(|step| matches!(step, SessionStep::Login(_))).count();
+            assert_eq!(db.session_edges(&session_id.parse()?).await?.logins.len(), logins);
+            match expected {
+                Ok(user) => {
+                    let authenticated = db.authenticate_session(&session_id).await?;
+                    assert_eq!(authenticated.user.user_id, users[user]);
+                    assert_eq!(Some(authenticated.session_login_id), last_login);
+                    assert_eq!(sessions[0].session_login_id, last_login);
+                    assert_eq!(sessions[0].user.as_ref().map(|user| user.user_id.clone()), Some(users[user].clone()));
+                    assert_eq!(db.session_edges(&session_id.parse()?).await?.active_logins().count(), 1);
+                },
+                Err(reason) => {
+                    assert_eq!(db.authenticate_session(&session_id).await.unwrap_err(), DatastoreError::Unauthenticated(reason));
+                    assert!(sessions[0].session_login_id.is_none());
+                    assert!(sessions[0].user.is_none());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn auth_data_reads_free_text_and_structured_values() -> Result<()> {
+        let session_data = SessionData {
+            created: None,
+            session_login_id: None,
+            login: None,
+            logout: None,
+            auth_data: Some(AuthData::new(AuthMethod::BankId).ip("10.0.0.1").assurance_level("loa3").idp_transaction_id("order-1")),
+            expires: None,
+            idle_timeout: None,
+            last_active: None,
+            user_id: None
+        };
+        let mut item = serde_dynamodb::to_hashmap(&session_data)?;
+        let method = item.get("auth_data").and_then(|auth_data| auth_data.m.as_ref()).and_then(|auth_data| auth_data.get("method"));
+        assert_eq!(method.and_then(|method| method.s.as_deref()), Some("bankid"));
+        let decoded: SessionData = serde_dynamodb::from_hashmap(item.clone())?;
+        assert_eq!(decoded.auth_data, session_data.auth_data);
+        let login = Vertex::SessionLogin(String::from("1"));
+        let edge = new_edge(&login, &EdgeType::SessionLogin, &login, Some(VertexData::SessionData(session_data.clone())));
+        match serde_dynamodb::from_hashmap::<Edge, _>(serde_dynamodb::to_hashmap(&edge)?)?.data {
+            Some(VertexData::SessionData(decoded)) => assert_eq!(decoded.auth_data, session_data.auth_data),
+            _ => panic!("session_login without SessionData")
+        }
+
+        item.insert(String::from("auth_data"), rusoto_dynamodb::AttributeValue { s: Some(String::from("logged in from ip 123.456.7.8")), ..Default::default() });
+        let decoded: SessionData = serde_dynamodb::from_hashmap(item.clone())?;
+        assert_eq!(decoded.auth_data, Some(AuthData::from("logged in from ip 123.456.7.8")));
+
+        item.remove("auth_data");
+        let decoded: SessionData = serde_dynamodb::from_hashmap(item)?;
+        assert_eq!(decoded.auth_data, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_logins_keep_auth_data_for_audits() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+        let first = db.session_new().await?.session_id;
+        db.session_auth(&first, &user_id, "legacy text").await?;
+        db.session_logout(&first).await?;
+        let second = db.session_new().await?.session_id;
+        let bankid = AuthData::new(AuthMethod::BankId).ip("10.0.0.1").user_agent("Mozilla/5.0").assurance_level("loa3").idp_transaction_id("order-1");
+        db.session_auth(&second, &user_id, bankid.clone()).await?;
+
+        let logins = db.get_user_logins(&user_id).await?;
+        assert_eq!(logins.len(), 2);
+        assert_eq!(logins[0].session_id, second);
+        assert_eq!(logins[0].auth_data, Some(bankid));
+        assert!(logins[0].logout.is_none());
+        assert_eq!(logins[1].session_id, first);
+        assert_eq!(logins[1].auth_data.as_ref().and_then(|auth_data| auth_data.description.as_deref()), Some("legacy text"));
+        assert!(logins[1].logout.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_document_completed_links_document() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+        let doc_id: Vertex = db.new_document(&user_id.parse()?, Some("Avtal"), Some("application/pdf")).await?.parse()?;
+
+        db.upload_document_completed(&doc_id.to_string(), "motrice-insignia", &format!("uploads/{}", doc_id), "abc123").await?;
+
+        let documents = db.get_user_documents(&user_id).await?;
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].doc_id, doc_id.to_string());
+
+        assert_eq!(db.store.query_edges_from(&doc_id.to_string(), Some("doc_"), &PageRequest::default()).await?.edges.len(), 3);
+        let edges = db.get_vertex_with_edges(&doc_id.to_string()).await?;
+        assert!(edges.iter().any(|edge| edge.vertex_b == "SHA256-abc123"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_verification_checks_key_and_checksum() -> Result<()> {
+        let objects = Arc::new(MemoryObjectStore::new());
+        let db = memory_db().with_upload_verification(objects.clone());
+        let user_id = tolvan(&db).await?;
+        let doc_id = db.new_document(&user_id.parse()?, None, None).await?;
+        let key = format!("uploads/{}", doc_id);
+        // SHA-256 of "hello"
+        let sha256 = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+        assert!(matches!(db.upload_document_completed(&doc_id, "docs", &key, sha256).await, Err(DatastoreError::UploadMismatch(_))));
+        objects.put_object("docs", "uploads/elsewhere", b"hello");
+        assert!(matches!(db.upload_document_completed(&doc_id, "docs", "uploads/elsewhere", sha256).await, Err(DatastoreError::UploadMismatch(_))));
+        objects.put_object("docs", &key, b"hello!");
+        assert!(matches!(db.upload_document_completed(&doc_id, "docs", &key, sha256).await, Err(DatastoreError::UploadMismatch(_))));
+        assert_eq!(document_status(&db, &doc_id).await?, DocumentStatus::PendingUpload);
+
+        objects.put_object("docs", &key, b"hello");
+        db.upload_document_completed(&doc_id, "docs", &key, &sha256.to_uppercase()).await?;
+        let document = db.get_document(&doc_id).await?;
+        assert_eq!(document.metadata.as_ref().map(|data| data.status), Some(DocumentStatus::Uploaded));
+        assert_eq!(document.metadata.and_then(|data| data.size), Some(5));
+        assert_eq!(document.checksum.as_deref(), Some(sha256));
+        assert_eq!(db.find_documents_by_checksum(&sha256.to_uppercase()).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn duplicate_uploads_by_the_same_owner() -> Result<()> {
+        let db = memory_db();
+        let tolvan = tolvan(&db).await?;
+        let elvan = db.new_user("191111111111", "Elvan Elvansson", "Elvan", "Elvansson", None, None, None).await?;
+        let first = db.new_document(&tolvan.parse()?, None, None).await?;
+        db.upload_document_completed(&first, "bucket", "key", "abc123").await?;
+
+        let other_owner = db.new_document(&elvan.parse()?, None, None).await?;
+        let second = db.new_document(&tolvan.parse()?, None, None).await?;
+        let db = db.with_duplicate_policy(DuplicatePolicy::Reject);
+        db.upload_document_completed(&other_owner, "bucket", "key", "abc123").await?;
+        assert!(matches!(db.upload_document_completed(&second, "bucket", "key", "abc123").await, Err(DatastoreError::Duplicate(_))));
+        assert_eq!(document_status(&db, &second).await?, DocumentStatus::PendingUpload);
+
+        let db = db.with_duplicate_policy(DuplicatePolicy::Link);
+        db.upload_document_completed(&second, "bucket", "key", "abc123").await?;
+        assert_eq!(db.get_document(&second).await?.duplicate_of, Some(first.clone()));
+        assert_eq!(db.get_document(&other_owner).await?.duplicate_of, None);
+
+        let mut found: Vec<String> = db.find_documents_by_checksum("abc123").await?.into_iter().map(|doc| doc.doc_id).collect();
+        found.sort();
+        let mut expected = vec![first, other_owner, second];
+        expected.sort();
+        assert_eq!(found, expected);
+        assert!(db.find_documents_by_checksum("def456").await?.is_empty());
+
+        let upper = db.new_document(&tolvan.parse()?, None, None).await?;
+        let mixed = db.new_document(&tolvan.parse()?, None, None).await?;
+        db.store_edge(&new_edge(&upper.parse()?, &EdgeType::DocumentChecksum, &Vertex::ChecksumSha256(String::from("FED987")), None)).await?;
+        db.store_edge(&new_edge(&mixed.parse()?, &EdgeType::DocumentChecksum, &Vertex::ChecksumSha256(String::from("FeD987")), None)).await?;
+        let found: Vec<String> = db.find_documents_by_checksum("fed987").await?.into_iter().map(|doc| doc.doc_id).collect();
+        assert_eq!(found, vec![upper.clone()]);
+        assert!(db.normalize_document_checksum(&mixed).await?);
+        assert!(!db.normalize_document_checksum(&mixed).await?);
+        assert_eq!(db.get_document(&mixed).await?.checksum.as_deref(), Some("fed987"));
+        let mut found: Vec<String> = db.find_documents_by_checksum("fed987").await?.into_iter().map(|doc| doc.doc_id).collect();
+        found.sort();
+        let mut expected = vec![upper, mixed];
+        expected.sort();
+        assert_eq!(found, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reads_follow_every_page() -> Result<()> {
+        let db = GraphDb::with_store(MemoryStore::with_page_size(2));
+        let user_id = tolvan(&db).await?;
+        let user_vertex: Vertex = user_id.parse()?;
+        for n in 0..5 {
+            let doc_id = Vertex::Document(format!("doc-{}", n));
+            db.store_edge(&new_edge(&user_vertex, &EdgeType::DocumentOwner, &doc_id, None)).await?;
+        }
+
+        assert_eq!(db.get_user_documents(&user_id).await?.len(), 5);
+        let user = db.get_user(&user_id).await?;
+        assert_eq!(user.phone.as_deref(), Some("+46733414983"));
+
+        let mut next_token: Option<String> = None;
+        let mut pages = Vec::new();
+        loop {
+            let page = db.get_user_documents_page(&user_id, 3, next_token.as_deref()).await?;
+            pages.push(page.items.len());
+            next_token = page.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+        assert_eq!(pages, vec![2, 2, 1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_edges_fetches_pages_lazily() -> Result<()> {
+        let db = GraphDb::with_store(MemoryStore::with_page_size(2));
+        let checksum = Vertex::ChecksumSha256(String::from("abc123"));
+        for n in 0..5 {
+            let doc_id = Vertex::Document(format!("doc-{}", n));
+            db.store_edge(&new_edge(&doc_id, &EdgeType::DocumentChecksum, &checksum, None)).await?;
+        }
+
+        let edges: Vec<Edge> = db.stream_edges_to(&checksum.to_string()).try_collect().await?;
+        assert_eq!(edges.len(), 5);
+
+        let first: Vec<Result<Edge>> = db.stream_edges_to(&checksum.to_string()).take(3).collect().await;
+        assert_eq!(first.len(), 3);
+
+        let doc_edges: Vec<Edge> = db.stream_edges_from("Document-doc-0").try_collect().await?;
+        assert_eq!(doc_edges.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_edges_ends_after_error() {
+        let db = GraphDb::with_store(unavailable_store());
+        let edges: Vec<Result<Edge>> = db.stream_edges_from("User-1").collect().await;
+        assert_eq!(edges.len(), 1);
+        assert!(edges[0].is_err());
+    }
+
+    #[tokio::test]
+    async fn store_edges_retries_unprocessed_edges() -> Result<()> {
+        let db = memory_db();
+        let user = Vertex::User(String::from("1"));
+        let edges: Vec<Edge> = (0..30)
+            .map(|n| new_edge(&user, &EdgeType::DocumentOwner, &Vertex::Document(format!("doc-{}", n)), None))
+            .collect();
+
+        db.store.leave_unprocessed(3);
+        let report = db.store_edges(&edges).await?;
+        assert_eq!(report.written, 30);
+        assert!(report.failed.is_empty());
+        assert_eq!(db.get_user_documents("User-1").await?.len(), 30);
+
+        let report = db.store_edges(&[edges[0].clone(), edges[0].clone()]).await?;
+        assert_eq!(report.written, 1);
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct ThrottleCounter(std::sync::atomic::AtomicUsize);
+
+    impl RetryMetrics for ThrottleCounter {
+        fn on_throttle(&self, _operation: &str) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn store_edges_gives_up_on_persistently_unprocessed_edges() -> Result<()> {
+        let throttles = std::sync::Arc::new(ThrottleCounter::default());
+        let db = memory_db().with_retry_policy(RetryPolicy::new()
+            .max_attempts(3)
+            .base_delay(std::time::Duration::from_millis(1))
+            .metrics(throttles.clone()));
+        let user = Vertex::User(String::from("1"));
+        let edges: Vec<Edge> = (0..2)
+            .map(|n| new_edge(&user, &EdgeType::DocumentOwner, &Vertex::Document(format!("doc-{}", n)), None))
+            .collect();
+
+        db.store.leave_unprocessed(5);
+        let report = db.store_edges(&edges).await?;
+        assert_eq!(report.written, 1);
+        assert_eq!(report.failed.len(), 1);
+        assert!(report.failed[0].error.is_transient());
+        assert_eq!(throttles.0.load(std::sync::atomic::Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn store_edges_reports_failed_edges() -> Result<()> {
+        let db = GraphDb::with_store(unavailable_store());
+        let user = Vertex::User(String::from("1"));
+        let edges: Vec<Edge> = (0..3)
+            .map(|n| new_edge(&user, &EdgeType::DocumentOwner, &Vertex::Document(format!("doc-{}", n)), None))
+            .collect();
+
+        let report = db.store_edges(&edges).await?;
+        assert_eq!(report.written, 0);
+        assert_eq!(report.failed.len(), 3);
+        assert!(matches!(report.into_result(), Err(DatastoreError::Backend(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transactions_are_all_or_nothing() -> Result<()> {
+        let db = memory_db();
+        let user = Vertex::User(String::from("1"));
+        let owner = new_edge(&user, &EdgeType::DocumentOwner, &Vertex::Document(String::from("doc-1")), None);
+        let reader = new_edge(&user, &EdgeType::DocumentReader, &Vertex::Document(String::from("doc-2")), None);
+
+        let conflicting = vec![WriteOp::Put(owner.clone()), WriteOp::Put(reader.clone()), WriteOp::delete(&owner)];
+        assert!(db.transact(&conflicting).await.is_err());
+        assert!(db.store.is_empty());
+
+        let too_many: Vec<Edge> = (0..TRANSACT_WRITE_LIMIT + 1)
+            .map(|n| new_edge(&user, &EdgeType::DocumentOwner, &Vertex::Document(format!("doc-{}", n)), None))
+            .collect();
+        assert!(db.store_edges_atomic(&too_many).await.is_err());
+        assert!(db.store.is_empty());
+
+        db.store_edges_atomic(&[owner.clone(), reader]).await?;
+        assert_eq!(db.get_user_documents("User-1").await?.len(), 2);
+        db.transact(&[WriteOp::delete(&owner)]).await?;
+        assert_eq!(db.get_user_documents("User-1").await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn versioned_updates_detect_stale_edges() -> Result<()> {
+        let db = memory_db();
+        let doc = Vertex::Document(String::from("d"));
+        let edge = new_edge(&doc, &EdgeType::DocumentSelf, &doc, None);
+
+        let created = db.create_edge(&edge).await?;
+        assert_eq!(created.version, Some(1));
+        assert!(matches!(db.create_edge(&edge).await, Err(DatastoreError::Conflict(_))));
+
+        let updated = db.update_edge(&created).await?;
+        assert_eq!(updated.version, Some(2));
+        assert!(matches!(db.update_edge(&created).await, Err(DatastoreError::Conflict(_))));
+
+        let stored = db.get_edge(&edge.vertex_a, &edge.edge).await?.unwrap();
+        assert_eq!(stored.version, Some(2));
+        db.update_edge(&stored).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_completion_requires_a_pending_document() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+        assert!(matches!(db.upload_document_completed("Document-unknown", "bucket", "key", "sha").await, Err(DatastoreError::NotFound(_))));
+
+        let doc_id = db.new_document(&user_id.parse()?, Some("Avtal"), Some("application/pdf")).await?;
+        db.upload_document_completed(&doc_id, "bucket", "key", "sha").await?;
+        assert!(matches!(db.upload_document_completed(&doc_id, "bucket", "key", "sha").await, Err(DatastoreError::InvalidTransition(_))));
+
+        let metadata = db.get_document(&doc_id).await?.metadata.unwrap();
+        assert_eq!(metadata.status, DocumentStatus::Uploaded);
+        assert_eq!(metadata.title.as_deref(), Some("Avtal"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_and_download_urls_use_the_configured_s3() -> Result<()> {
+        let credentials = rusoto_credential::AwsCredentials::new("minio", "minio123", None, None);
+        let db = memory_db().with_s3(S3Config::endpoint("local", "http://localhost:9000").credentials(credentials));
+        let user_id = tolvan(&db).await?;
+
+        let options = PresignOptions::new().content_type("application/pdf").content_length(10).max_size(5);
+        assert!(matches!(db.upload_document_url("docs", &user_id.parse()?, &options).await, Err(DatastoreError::InvalidInput(_))));
+        assert!(db.get_user_documents(&user_id).await?.is_empty());
+
+        let (doc_id, url) = db.upload_document_url("docs", &user_id.parse()?, &options.max_size(100)).await?;
+        assert!(url.starts_with(&format!("http://localhost:9000/docs/uploads/{}?", doc_id)));
+        assert!(matches!(db.download_document_url(&doc_id, std::time::Duration::from_secs(60)).await, Err(DatastoreError::NotFound(_))));
+
+        db.upload_document_completed(&doc_id, "docs", &format!("uploads/{}", doc_id), "abc123").await?;
+        let url = db.download_document_url(&doc_id, std::time::Duration::from_secs(60)).await?;
+        assert!(url.contains("X-Amz-Expires=60"));
+        Ok(())
+    }
+
+    async fn document_status(db: &GraphDb<MemoryStore>, doc_id: &str) -> Result<DocumentStatus> {
+        Ok(db.get_document(doc_id).await?.metadata.unwrap().status)
+    }
+
+    #[tokio::test]
+    async fn document_lifecycle_follows_sign_requests() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+        let doc_id = db.new_document(&user_id.parse()?, None, None).await?;
+
+        assert!(matches!(db.request_signature(&doc_id, &user_id, &user_id, None, None).await, Err(DatastoreError::InvalidTransition(_))));
+        assert!(matches!(db.set_document_status(&doc_id, DocumentStatus::Signed).await, Err(DatastoreError::InvalidTransition(_))));
+        db.upload_document_completed(&doc_id, "bucket", "key", "sha").await?;
+
+        db.request_signature(&doc_id, &user_id, &user_id, None, None).await?;
+        assert_eq!(document_status(&db, &doc_id).await?, DocumentStatus::AwaitingSignatures);
+        db.cancel_sign_request(&doc_id, &user_id, &user_id).await?;
+        assert_eq!(document_status(&db, &doc_id).await?, DocumentStatus::Uploaded);
+        db.request_signature(&doc_id, &user_id, &user_id, None, None).await?;
+        db.sign_document(&doc_id, &user_id, &user_id, "pkcs7").await?;
+        assert_eq!(document_status(&db, &doc_id).await?, DocumentStatus::Signed);
+
+        db.set_document_status(&doc_id, DocumentStatus::Archived).await?;
+        assert!(matches!(db.set_document_status(&doc_id, DocumentStatus::Uploaded).await, Err(DatastoreError::InvalidTransition(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn session_auth_bumps_session_version() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+        let session = db.session_new().await?;
+        db.session_auth(&session.session_id, &user_id, "ip 127.0.0.1").await?;
+        db.session_logout(&session.session_id).await?;
+
+        let key = format!("{}|{}|{}", EdgeType::SessionSelf, session.session_id, session.session_id);
+        assert_eq!(db.get_edge(&session.session_id, &key).await?.unwrap().version, Some(2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authenticate_session_tells_why_it_fails() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+        let failure = |result: Result<AuthenticatedSession>| match result {
+            Err(DatastoreError::Unauthenticated(reason)) => Some(reason),
+            _ => None
+        };
+
+        assert_eq!(failure(db.authenticate_session("Session-unknown").await), Some(AuthFailure::UnknownSession));
+        assert_eq!(failure(db.authenticate_session(&user_id).await), Some(AuthFailure::UnknownSession));
+        assert_eq!(failure(db.authenticate_session("garbage").await), Some(AuthFailure::UnknownSession));
+
+        let session = db.session_new().await?;
+        assert_eq!(failure(db.authenticate_session(&session.session_id).await), Some(AuthFailure::NotLoggedIn));
+
+        let login = db.session_auth(&session.session_id, &user_id, "ip 127.0.0.1").await?;
+        let authenticated = db.authenticate_session(&session.session_id).await?;
+        assert_eq!(authenticated.user.user_id, user_id);
+        assert_eq!(Some(authenticated.session_login_id), login.session_login_id);
+        assert_eq!(authenticated.auth_data, Some(AuthData::from("ip 127.0.0.1")));
+
+        db.session_logout(&session.session_id).await?;
+        assert_eq!(failure(db.authenticate_session(&session.session_id).await), Some(AuthFailure::LoggedOut));
+
+        let db = db.with_session_lifetime(SessionLifetime::new().absolute(std::time::Duration::from_secs(0)));
+        let session = db.session_new().await?;
+        assert_eq!(failure(db.authenticate_session(&session.session_id).await), Some(AuthFailure::Expired));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn logout_everywhere_and_other_sessions() -> Result<()> {
+        let db = memory_db();
+        let tolvan = tolvan(&db).await?;
+        let elvan = db.new_user("191111111111", "Elvan Elvansson", "Elvan", "Elvansson", None, None, None).await?;
+        let mut sessions = Vec::new();
+        for _ in 0..3 {
+            let session = db.session_new().await?;
+            db.session_auth(&session.session_id, &tolvan, "ip 127.0.0.1").await?;
+            sessions.push(session.session_id);
+        }
+        let other = db.session_new().await?.session_id;
+        db.session_auth(&other, &elvan, "ip 127.0.0.2").await?;
+
+        let mut listed: Vec<String> = db.get_user_sessions(&tolvan).await?.into_iter().map(|session| session.session_id).collect();
+        listed.sort();
+        let mut expected = sessions.clone();
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        assert_eq!(db.logout_other_sessions(&tolvan, &sessions[0]).await?, 2);
+        let listed: Vec<String> = db.get_user_sessions(&tolvan).await?.into_iter().map(|session| session.session_id).collect();
+        assert_eq!(listed, vec![sessions[0].clone()]);
+        assert!(matches!(db.authenticate_session(&sessions[1]).await, Err(DatastoreError::Unauthenticated(AuthFailure::LoggedOut))));
+
+        let purged = db.session_new().await?.session_id;
+        db.session_auth(&purged, &tolvan, "ip 127.0.0.1").await?;
+        let purged_vertex: Vertex = purged.parse()?;
+        db.store.delete_edge(&purged, &edge_key(&purged_vertex, &EdgeType::SessionSelf, &purged_vertex)).await?;
+        assert_eq!(db.logout_everywhere(&tolvan).await?, 1);
+        assert!(db.get_user_sessions(&tolvan).await?.is_empty());
+        assert_eq!(db.logout_everywhere(&tolvan).await?, 0);
+        assert_eq!(db.authenticate_session(&other).await?.user.user_id, elvan);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sessions_expire_after_their_lifetime_or_idle_timeout() -> Result<()> {
+        let db = memory_db().with_session_lifetime(SessionLifetime::new().absolute(std::time::Duration::from_secs(0)));
+        let session = db.session_new().await?;
+        assert!(matches!(db.sessions_get(&session.session_id).await, Err(DatastoreError::Expired(_))));
+        assert!(matches!(db.touch_session(&session.session_id).await, Err(DatastoreError::Expired(_))));
+
+        let db = db.with_session_lifetime(SessionLifetime::new().absolute(std::time::Duration::from_secs(86400)).idle(std::time::Duration::from_secs(600)));
+        let user_id = tolvan(&db).await?;
+        let session = db.session_new().await?;
+        db.session_auth(&session.session_id, &user_id, "ip 127.0.0.1").await?;
+        let session_vertex: Vertex = session.session_id.parse()?;
+        let ttl = session_self(&db, &session_vertex).await?.expires_at.unwrap();
+        assert!(ttl > Utc::now().timestamp() && ttl <= Utc::now().timestamp() + 600);
+        let lifetime_ends_at = db.edges_from(&session.session_id, Some("session_")).await?.into_iter()
+            .filter(|edge| edge.edge != edge_key(&session_vertex, &EdgeType::SessionSelf, &session_vertex))
+            .map(|edge| edge.expires_at.unwrap())
+            .collect::<Vec<i64>>();
+        assert_eq!(lifetime_ends_at.len(), 2);
+        assert!(lifetime_ends_at.iter().all(|expires_at| *expires_at > Utc::now().timestamp() + 86000));
+
+        let edge = session_self(&db, &session_vertex).await?;
+        let idle = match edge.data.clone() {
+            Some(VertexData::SessionData(data)) => SessionData { last_active: Some((Utc::now() - chrono::Duration::seconds(300)).to_rfc3339()), ..data },
+            _ => panic!("session_self without SessionData")
+        };
+        db.update_edge(&Edge { data: Some(VertexData::SessionData(idle.clone())), expires_at: Some(ttl - 300), ..edge }).await?;
+        db.touch_session(&session.session_id).await?;
+        let touched = session_self(&db, &session_vertex).await?.expires_at.unwrap();
+        assert!(touched >= ttl);
+        let others = db.edges_from(&session.session_id, Some("session_")).await?.into_iter()
+            .filter(|edge| edge.edge != edge_key(&session_vertex, &EdgeType::SessionSelf, &session_vertex))
+            .map(|edge| edge.expires_at.unwrap())
+            .collect::<Vec<i64>>();
+        assert_eq!(others, lifetime_ends_at);
+
+        let edge = session_self(&db, &session_vertex).await?;
+        let expired = SessionData { last_active: Some((Utc::now() - chrono::Duration::seconds(601)).to_rfc3339()), ..idle };
+        db.update_edge(&Edge { data: Some(VertexData::SessionData(expired)), ..edge }).await?;
+        assert!(matches!(db.sessions_get(&session.session_id).await, Err(DatastoreError::Expired(_))));
+        assert!(matches!(db.session_auth(&session.session_id, &user_id, "ip 127.0.0.1").await, Err(DatastoreError::Expired(_))));
+
+        db.store.delete_edge(&session.session_id, &edge_key(&session_vertex, &EdgeType::SessionSelf, &session_vertex)).await?;
+        assert!(matches!(db.sessions_get(&session.session_id).await, Err(DatastoreError::NotFound(_))));
+        assert!(matches!(db.authenticate_session(&session.session_id).await, Err(DatastoreError::Unauthenticated(AuthFailure::UnknownSession))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn idle_only_sessions_expire_every_edge() -> Result<()> {
+        let db = memory_db().with_session_lifetime(SessionLifetime::new().idle(std::time::Duration::from_secs(600)));
+        let user_id = tolvan(&db).await?;
+        let session = db.session_new().await?;
+        db.session_auth(&session.session_id, &user_id, "ip 127.0.0.1").await?;
+        let session_vertex: Vertex = session.session_id.parse()?;
+        let ttl = session_self(&db, &session_vertex).await?.expires_at.unwrap();
+        let login = db.edges_from(&session.session_id, Some("session_login")).await?;
+        let user = db.edges_from(&session.session_id, Some("session_user")).await?;
+        assert_eq!(login.len(), 1);
+        assert_eq!(login[0].expires_at, Some(ttl + 600));
+        assert_eq!(user[0].expires_at, Some(ttl + 600));
+
+        db.touch_session(&session.session_id).await?;
+        assert_eq!(db.edges_from(&session.session_id, Some("session_login")).await?[0].expires_at, Some(ttl + 600));
+
+        db.store.put_edge(&Edge { expires_at: Some(ttl - 1), ..login[0].clone() }).await?;
+        db.store.put_edge(&Edge { expires_at: None, ..user[0].clone() }).await?;
+        db.touch_session(&session.session_id).await?;
+        let touched = session_self(&db, &session_vertex).await?.expires_at.unwrap();
+        assert_eq!(db.edges_from(&session.session_id, Some("session_login")).await?[0].expires_at, Some(touched + 600));
+        assert_eq!(db.edges_from(&session.session_id, Some("session_user")).await?[0].expires_at, Some(touched + 600));
+        db.authenticate_session(&session.session_id).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn organisations_and_members() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+        let org = db.new_organisation("556677-8899", "Motrice AB").await?;
+        assert!(matches!(db.new_organisation("556677-8899", "Other AB").await, Err(DatastoreError::Conflict(_))));
+        assert_eq!(db.get_organisation(&org.id).await?, org);
+        assert_eq!(db.get_organisation_by_org_no("556677-8899").await?, org);
+
+        db.organisation_add_user(&org.id, &user_id, "admin").await?;
+        db.organisation_add_user(&org.id, &user_id, "signatory").await?;
+        let memberships = db.get_user_organisations(&user_id).await?;
+        assert_eq!(memberships.len(), 1);
+        assert_eq!(memberships[0].organisation, org);
+        assert_eq!(memberships[0].role, "signatory");
+        assert_eq!(db.get_organisation_members(&org.id).await?[0].user_id, user_id);
+
+        let renamed = Organisation { org_no: String::from("556677-0000"), name: String::from("Motrice Group AB"), ..org.clone() };
+        db.update_organisation(&renamed).await?;
+        assert_eq!(db.get_organisation(&org.id).await?, renamed);
+        assert!(matches!(db.get_organisation_by_org_no("556677-8899").await, Err(DatastoreError::NotFound(_))));
+        assert_eq!(db.get_organisation_by_org_no("556677-0000").await?, renamed);
+        let other = db.new_organisation("556677-8899", "Other AB").await?;
+        assert!(matches!(db.update_organisation(&Organisation { org_no: String::from("556677-0000"), ..other }).await, Err(DatastoreError::Conflict(_))));
+
+        db.organisation_remove_user(&org.id, &user_id).await?;
+        assert!(db.get_user_organisations(&user_id).await?.is_empty());
+        assert!(matches!(db.organisation_add_user("Organisation-missing", &user_id, "admin").await, Err(DatastoreError::NotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_organisations_cannot_share_a_number() -> Result<()> {
+        let db = memory_db();
+        let (first, second) = futures::join!(
+            db.new_organisation("556677-8899", "Motrice AB"),
+            db.new_organisation("556677-8899", "Other AB")
+        );
+        assert!(first.is_ok() != second.is_ok());
+        assert!(matches!(first.and(second), Err(DatastoreError::Conflict(_))));
+        assert_eq!(db.edges_to("OrgNo-556677-8899", Some("org_no")).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_document_loads_the_whole_aggregate() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+        let org = db.new_organisation("556677-8899", "Motrice AB").await?;
+        let doc_id = db.new_document(&user_id.parse()?, None, None).await?;
+        let doc: Vertex = doc_id.parse()?;
+        db.store_edges_atomic(&[
+            new_edge(&org.id.parse()?, &EdgeType::DocumentReader, &doc, None),
+            new_edge(&doc, &EdgeType::DocumentSignRequest, &org.id.parse()?, None),
+            new_edge(&doc, &EdgeType::DocumentSignature, &user_id.parse()?, None)
+        ]).await?;
+        db.upload_document_completed(&doc_id, "bucket", "uploads/Document-d", "abc123").await?;
+
+        let document = db.get_document(&doc_id).await?;
+        assert!(matches!(&document.owners[..], [LegalEntity::User(user)] if user.user_id == user_id));
+        assert!(matches!(&document.readers[..], [LegalEntity::Org(reader)] if reader == &org));
+        assert!(matches!(&document.signature_reqs[..], [LegalEntity::Org(_)]));
+        assert!(matches!(&document.signatures[..], [LegalEntity::User(_)]));
+        assert_eq!(document.s3.map(|s3| s3.key).as_deref(), Some("uploads/Document-d"));
+        assert_eq!(document.checksum.as_deref(), Some("abc123"));
+
+        assert!(matches!(db.get_document("Document-missing").await, Err(DatastoreError::NotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sign_request_workflow() -> Result<()> {
+        let db = memory_db();
+        let owner = tolvan(&db).await?;
+        let signer = db.new_user("198112289874", "Elva Elvasson", "Elva", "Elvasson", None, None, None).await?;
+        let org = db.new_organisation("556677-8899", "Motrice AB").await?;
+        db.organisation_add_user(&org.id, &signer, "signatory").await?;
+        let doc_id = db.new_document(&owner.parse()?, None, None).await?;
+        db.upload_document_completed(&doc_id, "bucket", "key", "sha").await?;
+
+        let due = Utc::now() + chrono::Duration::days(7);
+        let request = db.request_signature(&doc_id, &signer, &owner, Some(due), Some("Please sign")).await?;
+        assert_eq!(request.status, SignRequestStatus::Pending);
+        assert!(matches!(db.request_signature(&doc_id, &signer, &owner, None, None).await, Err(DatastoreError::Conflict(_))));
+        assert!(matches!(db.request_signature(&doc_id, &org.id, &signer, None, None).await, Err(DatastoreError::Unauthorized(_))));
+        assert!(matches!(db.request_signature(&doc_id, &org.id, &org.id, None, None).await, Err(DatastoreError::InvalidVertex(_))));
+        db.request_signature(&doc_id, &org.id, &owner, None, None).await?;
+        assert_eq!(db.get_pending_sign_requests(&signer).await?[0].message.as_deref(), Some("Please sign"));
+        assert!(!db.is_fully_signed(&doc_id).await?);
+
+        assert!(matches!(db.sign_document(&doc_id, &signer, &owner, "pkcs7").await, Err(DatastoreError::Unauthorized(_))));
+        db.sign_document(&doc_id, &signer, &signer, "pkcs7").await?;
+        assert!(matches!(db.sign_document(&doc_id, &signer, &signer, "pkcs7").await, Err(DatastoreError::Conflict(_))));
+        assert!(db.get_pending_sign_requests(&signer).await?.is_empty());
+
+        db.decline_sign_request(&doc_id, &org.id, &signer).await?;
+        assert!(!db.is_fully_signed(&doc_id).await?);
+        db.request_signature(&doc_id, &org.id, &owner, None, None).await?;
+        assert_eq!(db.get_document(&doc_id).await?.signature_reqs.len(), 1);
+        db.sign_document(&doc_id, &org.id, &signer, "pkcs7").await?;
+        assert!(db.is_fully_signed(&doc_id).await?);
+
+        let document = db.get_document(&doc_id).await?;
+        assert!(document.signature_reqs.is_empty());
+        assert_eq!(document.signatures.len(), 2);
+
+        db.request_signature(&doc_id, &owner, &owner, None, None).await?;
+        assert!(!db.is_fully_signed(&doc_id).await?);
+        assert!(matches!(db.cancel_sign_request(&doc_id, &owner, &signer).await, Err(DatastoreError::Unauthorized(_))));
+        db.cancel_sign_request(&doc_id, &owner, &owner).await?;
+        assert!(db.is_fully_signed(&doc_id).await?);
+
+        db.request_signature(&doc_id, &signer, &owner, None, None).await?;
+        let document = db.get_document(&doc_id).await?;
+        assert!(matches!(&document.signature_reqs[..], [LegalEntity::User(user)] if user.user_id == signer));
+        assert!(matches!(&document.signatures[..], [LegalEntity::Org(_)]));
+        assert!(db.verify_signatures(&doc_id).await?.signatures.iter().all(|check| check.signer_id != signer));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signatures_are_bound_to_the_checksum() -> Result<()> {
+        let db = memory_db();
+        let user_id = tolvan(&db).await?;
+        let doc_id = db.new_document(&user_id.parse()?, None, None).await?;
+        db.upload_document_completed(&doc_id, "bucket", "key", "abc123").await?;
+        db.request_signature(&doc_id, &user_id, &user_id, None, None).await?;
+        db.sign_document(&doc_id, &user_id, &user_id, "MIIGbwYJKoZIhvcNAQcCoIIGYDCCBlwCAQEx").await?;
+
+        let verification = db.verify_signatures(&doc_id).await?;
+        assert!(!verification.checksum_changed());
+        let signature = verification.signatures[0].signature.as_ref().unwrap();
+        assert_eq!(signature.checksum, "SHA256-abc123");
+        assert_eq!(signature.payload, "MIIGbwYJKoZIhvcNAQcCoIIGYDCCBlwCAQEx");
+        assert_eq!(signature.signed_by, user_id);
+
+        let doc: Vertex = doc_id.parse()?;
+        db.transact(&[
+            WriteOp::delete(&new_edge(&doc, &EdgeType::DocumentChecksum, &Vertex::ChecksumSha256(String::from("abc123")), None)),
+            WriteOp::Put(new_edge(&doc, &EdgeType::DocumentChecksum, &Vertex::ChecksumSha256(String::from("ABC123")), None))
+        ]).await?;
+        assert!(!db.verify_signatures(&doc_id).await?.checksum_changed());
+
+        db.transact(&[
+            WriteOp::delete(&new_edge(&doc, &EdgeType::DocumentChecksum, &Vertex::ChecksumSha256(String::from("ABC123")), None)),
+            WriteOp::Put(new_edge(&doc, &EdgeType::DocumentChecksum, &Vertex::ChecksumSha256(String::from("def456")), None))
+        ]).await?;
+        let verification = db.verify_signatures(&doc_id).await?;
+        assert!(verification.checksum_changed());
+        assert!(!verification.signatures[0].matches_checksum);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reader_acl_and_authorization() -> Result<()> {
+        let db = memory_db();
+        let owner = tolvan(&db).await?;
+        let reader = db.new_user("198112289874", "Elva Elvasson", "Elva", "Elvasson", None, None, None).await?;
+        let member = db.new_user("197001011234", "Tretton Trettonsson", "Tretton", "Trettonsson", None, None, None).await?;
+        let org = db.new_organisation("556677-8899", "Motrice AB").await?;
+        db.organisation_add_user(&org.id, &member, "employee").await?;
+        let doc_id = db.new_document(&owner.parse()?, None, None).await?;
+
+        db.authorize(&owner, &doc_id, DocumentAction::ManageAccess).await?;
+        assert!(matches!(db.authorize(&reader, &doc_id, DocumentAction::Read).await, Err(DatastoreError::Unauthorized(_))));
+        assert!(matches!(db.grant_reader(&doc_id, &reader, &reader).await, Err(DatastoreError::Unauthorized(_))));
+
+        db.grant_reader(&doc_id, &reader, &owner).await?;
+        db.grant_reader(&doc_id, &org.id, &owner).await?;
+        assert_eq!(db.list_document_acl(&doc_id).await?.len(), 3);
+        db.authorize(&reader, &doc_id, DocumentAction::Read).await?;
+        db.authorize(&member, &doc_id, DocumentAction::Read).await?;
+        assert!(matches!(db.authorize(&member, &doc_id, DocumentAction::Write).await, Err(DatastoreError::Unauthorized(_))));
+
+        assert!(matches!(db.revoke_access(&doc_id, &org.id, &reader).await, Err(DatastoreError::Unauthorized(_))));
+        db.revoke_access(&doc_id, &org.id, &owner).await?;
+        assert!(matches!(db.authorize(&member, &doc_id, DocumentAction::Read).await, Err(DatastoreError::Unauthorized(_))));
+        assert!(matches!(db.revoke_access(&doc_id, &owner, &owner).await, Err(DatastoreError::Conflict(_))));
+        assert_eq!(db.list_document_acl(&doc_id).await?, vec![
+            AclEntry { entity_id: owner.clone(), role: AclRole::Owner },
+            AclEntry { entity_id: reader.clone(), role: AclRole::Reader }
+        ]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stale_revoke_conflicts() -> Result<()> {
+        let db = memory_db();
+        let first = tolvan(&db).await?;
+        let second = db.new_user("198112289874", "Elva Elvasson", "Elva", "Elvasson", None, None, None).await?;
+        let doc_id = db.new_document(&first.parse()?, None, None).await?;
+        let doc: Vertex = doc_id.parse()?;
+        db.store_edge(&new_edge(&second.parse()?, &EdgeType::DocumentOwner, &doc, None)).await?;
+
+        let revoke_first = db.revoke_access_ops(&doc, &first.parse()?).await?;
+        let revoke_second = db.revoke_access_ops(&doc, &second.parse()?).await?;
+        db.transact(&revoke_first).await?;
+        assert!(matches!(db.transact(&revoke_second).await, Err(DatastoreError::Conflict(_))));
+        assert_eq!(db.list_document_acl(&doc_id).await?, vec![AclEntry { entity_id: second, role: AclRole::Owner }]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backend_errors_are_not_empty_results() {
+        let db = GraphDb::with_store(unavailable_store());
+        assert!(matches!(db.get_user("User-1").await, Err(DatastoreError::Backend(_))));
+        assert!(matches!(db.sessions_get("Session-1").await, Err(DatastoreError::Backend(_))));
+        assert!(matches!(db.get_user_documents("User-1").await, Err(DatastoreError::Backend(_))));
+        assert!(matches!(db.get_users_by_personal_number("191212121212").await, Err(DatastoreError::Backend(_))));
+    }
+
+    #[tokio::test]
+    async fn corrupt_edges_fail_strict_and_are_skipped_lenient() -> Result<()> {
+        let db = GraphDb::with_store(MemoryStore::new().with_corrupt_items());
+        let user_id = db.new_user("191212121212", "Tolvan Tolvansson", "Tolvan", "Tolvansson", None, None, None).await?;
+        assert!(matches!(db.get_user(&user_id).await, Err(DatastoreError::Serialization(_))));
+
+        let db = db.with_decode_mode(DecodeMode::Lenient);
+        assert_eq!(db.get_user(&user_id).await?.given_name.as_deref(), Some("Tolvan"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn logout_everywhere_retries_a_conflicting_logout() -> Result<()> {
+        let conflict = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let armed = conflict.clone();
+        let db = GraphDb::with_store(MemoryStore::new().with_fault(move |method| match method {
+            "transact_write" if armed.swap(false, std::sync::atomic::Ordering::SeqCst) => Some(DatastoreError::Conflict(String::from("transaction cancelled"))),
+            _ => None
+        }));
+        let user_id = db.new_user("191212121212", "Tolvan Tolvansson", "Tolvan", "Tolvansson", None, None, None).await?;
+        let session = db.session_new().await?;
+        db.session_auth(&session.session_id, &user_id, "ip 127.0.0.1").await?;
+
+        conflict.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(db.logout_everywhere(&user_id).await?, 1);
+        assert!(matches!(db.authenticate_session(&session.session_id).await, Err(DatastoreError::Unauthenticated(AuthFailure::LoggedOut))));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_vertex_and_edge_type() {
+        assert!(matches!("Nonsense-1".parse::<Vertex>(), Err(DatastoreError::InvalidVertex(_))));
+        assert!(matches!("User".parse::<Vertex>(), Err(DatastoreError::InvalidVertex(_))));
+        assert!(matches!("nonsense|User-1|User-1".parse::<EdgeType>(), Err(DatastoreError::InvalidEdgeType(_))));
+    }
 }
@@ -3,40 +3,18 @@ extern crate rusoto_dynamodb;
 extern crate uuid;
 extern crate serde;
 extern crate serde_dynamodb;
-#[macro_use] extern crate serde_derive;
+extern crate serde_derive;
 extern crate tokio;
 extern crate chrono;
-use chrono::{DateTime, Utc};
-
-use std::collections::HashMap;
-use rusoto_core::Region;
 use rusoto_dynamodb::{
-    AttributeDefinition, 
-    AttributeValue,
-    CreateTableInput, 
     DeleteTableInput, 
     DynamoDb, 
-    DynamoDbClient, 
-    GetItemInput,
-    GlobalSecondaryIndex,
-    KeySchemaElement, 
-    ListTablesInput, 
-    Projection,
-    ProvisionedThroughput,
-    PutItemInput,
-    QueryInput,
     ScanInput
 };
 use anyhow::{Result};
 
-use uuid::Uuid;
-
-pub mod domain;
-
 use insignia_datastore::domain::*;
 
-use insignia_datastore::*;
-
 // pub type GenericError = Box<dyn std::error::Error + Send + Sync>;
 // pub type Result<T> = std::result::Result<T, GenericError>;
 
@@ -47,14 +25,9 @@ pub async fn main() -> Result<()> {
 
         println!("Hello, world!");
 
-    let region = Region::Custom {
-        name: "eu-north-1".to_owned(),
-        endpoint: "http://localhost:8000".to_owned(),
-    };
-
     let client = insignia_datastore::GraphDb::new_with_region("eu-north-1", "http://localhost:8000");
 
-    match client.client.delete_table(DeleteTableInput{table_name: String::from("bm-test-table")}).await {
+    match client.store.client.delete_table(DeleteTableInput{table_name: String::from("bm-test-table")}).await {
         Ok(_) => println!("Deleted table"),
         Err(_) => println!("Delete failed")
     }
@@ -87,11 +60,11 @@ doc-nnnn    link            "permissons"
 */
     client.create_table().await?;
 
-    let mut user1 = client.new_user("191212121212", "Tolvan Tolvansson", "Tolvan", "Tolvansson", Some("tolvan.tolvansson@motrice.se"), Some("+46733414983"), None).await?;
+    let user1 = client.new_user("191212121212", "Tolvan Tolvansson", "Tolvan", "Tolvansson", Some("tolvan.tolvansson@motrice.se"), Some("+46733414983"), None).await?;
     
-    match client.upload_document_url("motrice-insignia", &user1.parse()?).await {
-        Ok(todo_doc_id) => {
-            match client.upload_document_completed(&todo_doc_id, "foobucket", "barkey", "1234checksum").await {
+    match client.upload_document_url("motrice-insignia", &user1.parse()?, &insignia_datastore::PresignOptions::new()).await {
+        Ok((doc_id, _url)) => {
+            match client.upload_document_completed(&doc_id, "foobucket", "barkey", "1234checksum").await {
                 Ok(_) => println!("upload doc completed"),
                 Err(err) => println!("upload doc err {}", err)
             }
@@ -99,12 +72,16 @@ doc-nnnn    link            "permissons"
         Err(err) => println!("Error {}", err)
     }
 
-    let scan_edges : Vec<Edge> = match client.client.scan(ScanInput{
-        table_name: String::from("insignia-docs"),
+    let scan_edges : Vec<Edge> = match client.store.client.scan(ScanInput{
+        table_name: client.store.table_name.clone(),
         ..ScanInput::default()
     }).await {
             Ok(res) => {
-                res.items.unwrap_or_else(|| vec![]).into_iter().map(|item| serde_dynamodb::from_hashmap(item).unwrap()).collect()
+                let output = insignia_datastore::store::decode_items(res.items.unwrap_or_default());
+                for corrupt in &output.corrupt {
+                    println!("Corrupt edge {}", corrupt);
+                }
+                output.edges
             },
             Err(err) =>  {
                 println!("Error query{:?}", err);
@@ -126,7 +103,7 @@ doc-nnnn    link            "permissons"
         }
     }
 
-    let users = client.get_users_by_personal_number("191212121212").await;
+    let users = client.get_users_by_personal_number("191212121212").await?;
     for item in &users {
         println!("Tolvan user: {}", item);
     }
@@ -134,21 +111,21 @@ doc-nnnn    link            "permissons"
     match client.session_new().await {
         Ok(session) => {
             println!("new session:            {}", &session);
-            let sessions = client.sessions_get(&session.session_id).await;
+            let sessions = client.sessions_get(&session.session_id).await?;
             for loaded_sess in &sessions {
                 println!("Loaded the new session: {}", &loaded_sess);
             }
-            match client.session_auth(&session.session_id, &users[0].user_id, "logged in from ip 123.456.7.8").await {
+            match client.session_auth(&session.session_id, &users[0].user_id, insignia_datastore::domain::AuthData::new(insignia_datastore::domain::AuthMethod::BankId).ip("123.456.7.8")).await {
                 Ok(sess_auth) =>  {
                     println!("auth session:            {}", &session);
-                    let sessions =  client.sessions_get(&sess_auth.session_id).await;
+                    let sessions =  client.sessions_get(&sess_auth.session_id).await?;
                     for loaded_sess in &sessions {
                         println!("Loaded the new session auth: {}", &loaded_sess);
                     }
                     println!("logout session:            {}", &session);
                     match client.session_logout(&sess_auth.session_id).await {
                         Ok(_) => {
-                            let sessions =  client.sessions_get(&sess_auth.session_id).await;
+                            let sessions =  client.sessions_get(&sess_auth.session_id).await?;
                             for loaded_sess in &sessions {
                                 println!("Loaded the new session logout: {}", &loaded_sess);
                             }
@@ -163,9 +140,9 @@ doc-nnnn    link            "permissons"
         Err(err) => println!("Error while creating new session {}", err)
     }
 
-    match client.upload_document_url("motrice-insignia", &users[0].user_id.parse()?).await {
-        Ok(todo_doc_id) => {
-            match client.upload_document_completed(&todo_doc_id, "somebucketname", "somekeyval", "somesha").await {
+    match client.upload_document_url("motrice-insignia", &users[0].user_id.parse()?, &insignia_datastore::PresignOptions::new()).await {
+        Ok((doc_id, _url)) => {
+            match client.upload_document_completed(&doc_id, "somebucketname", "somekeyval", "somesha").await {
                 Ok(_) => println!("upload doc completed"),
                 Err(err) => println!("upload doc err {}", err)
             }
@@ -173,7 +150,7 @@ doc-nnnn    link            "permissons"
         Err(err) => println!("Error {}", err)
     }
 
-    let user_documents =  client.get_user_documents(&users[0].user_id).await;
+    let user_documents =  client.get_user_documents(&users[0].user_id).await?;
     for item in &user_documents {
         println!("User document: {}", &item);
     }
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use rusoto_core::{HttpClient, RusotoError};
+use rusoto_credential::StaticProvider;
+use rusoto_s3::{GetObjectError, GetObjectRequest, HeadObjectError, HeadObjectRequest, S3, S3Client};
+use sha2::{Digest, Sha256};
+
+use crate::error::{DatastoreError, Result};
+use crate::presign::S3Config;
+use crate::retry::RetryPolicy;
+
+/// Read access to stored documents, used to verify what a client claims it uploaded.
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Size in bytes of the object at `key` in `bucket`, or `None` if there is no such object.
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<Option<u64>>;
+
+    /// Hex encoded SHA-256 of the content of the object, or `None` if there is no such object.
+    async fn object_sha256(&self, bucket: &str, key: &str) -> Result<Option<String>>;
+}
+
+/// Objects in S3, or in anything speaking its API such as MinIO.
+pub struct S3ObjectStore {
+    client: S3Client,
+    retry_policy: RetryPolicy
+}
+
+impl S3ObjectStore {
+    /// Fails with `DatastoreError::Backend` if the HTTP client for static credentials cannot set up TLS.
+    pub fn from_config(s3: &S3Config, retry_policy: RetryPolicy) -> Result<S3ObjectStore> {
+        let client = match &s3.credentials {
+            Some(credentials) => S3Client::new_with(
+                HttpClient::new().map_err(|err| DatastoreError::Backend(format!("Failed to create request dispatcher: {}", err)))?,
+                StaticProvider::from(credentials.clone()),
+                s3.region.clone()
+            ),
+            None => S3Client::new(s3.region.clone())
+        };
+        Ok(S3ObjectStore {
+            client,
+            retry_policy
+        })
+    }
+}
+
+/// HEAD responses have no body, so a missing key comes back as an unparsed 404.
+fn missing<E>(err: &RusotoError<E>) -> bool {
+    matches!(err, RusotoError::Unknown(res) if res.status.as_u16() == 404)
+}
+
+#[async_trait]
+impl ObjectStore for S3ObjectStore {
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<Option<u64>> {
+        let input = HeadObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            ..Default::default()
+        };
+        self.retry_policy.run("HeadObject", || async {
+            match self.client.head_object(input.clone()).await {
+                Ok(output) => Ok(Some(output.content_length.unwrap_or(0) as u64)),
+                Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(None),
+                Err(err) if missing(&err) => Ok(None),
+                Err(err) => Err(DatastoreError::from(err))
+            }
+        }).await
+    }
+
+    async fn object_sha256(&self, bucket: &str, key: &str) -> Result<Option<String>> {
+        let input = GetObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            ..Default::default()
+        };
+        self.retry_policy.run("GetObject", || async {
+            let output = match self.client.get_object(input.clone()).await {
+                Ok(output) => output,
+                Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+                Err(err) if missing(&err) => return Ok(None),
+                Err(err) => return Err(DatastoreError::from(err))
+            };
+            let body = match output.body {
+                Some(body) => body,
+                None => return Ok(Some(hex::encode(Sha256::digest(&[]))))
+            };
+            let hasher = body
+                .map_err(|err| DatastoreError::Unavailable(format!("Reading {} failed: {}", key, err)))
+                .try_fold(Sha256::new(), |mut hasher, chunk| async move {
+                    hasher.input(&chunk);
+                    Ok(hasher)
+                }).await?;
+            Ok(Some(hex::encode(hasher.result())))
+        }).await
+    }
+}
+
+/// Objects kept in memory, standing in for S3 in tests.
+#[derive(Default)]
+pub struct MemoryObjectStore {
+    objects: Mutex<HashMap<(String, String), Vec<u8>>>
+}
+
+impl MemoryObjectStore {
+    pub fn new() -> MemoryObjectStore {
+        MemoryObjectStore::default()
+    }
+
+    pub fn put_object(&self, bucket: &str, key: &str, content: &[u8]) {
+        self.objects.lock().unwrap().insert((bucket.to_owned(), key.to_owned()), content.to_vec());
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MemoryObjectStore {
+    async fn head_object(&self, bucket: &str, key: &str) -> Result<Option<u64>> {
+        Ok(self.objects.lock().unwrap()
+            .get(&(bucket.to_owned(), key.to_owned()))
+            .map(|content| content.len() as u64))
+    }
+
+    async fn object_sha256(&self, bucket: &str, key: &str) -> Result<Option<String>> {
+        Ok(self.objects.lock().unwrap()
+            .get(&(bucket.to_owned(), key.to_owned()))
+            .map(|content| hex::encode(Sha256::digest(content))))
+    }
+}
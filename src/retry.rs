@@ -0,0 +1,249 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use rand::Rng;
+
+use crate::error::{DatastoreError, Result};
+
+/// Hooks for counting retries, e.g. to see how often DynamoDB throttles us. Every hook defaults to doing nothing.
+pub trait RetryMetrics: Send + Sync {
+    /// The backend throttled `operation`, either by rejecting it or by leaving items unprocessed.
+    fn on_throttle(&self, _operation: &str) {}
+
+    /// `operation` is sent again after `delay`. `attempt` is the number of attempts made so far.
+    fn on_retry(&self, _operation: &str, _attempt: u32, _delay: Duration) {}
+
+    /// `operation` still failed with a transient error after `attempts` attempts.
+    fn on_give_up(&self, _operation: &str, _attempts: u32) {}
+}
+
+/// Metrics hooks that ignore everything.
+pub struct NoMetrics;
+
+impl RetryMetrics for NoMetrics {}
+
+/// How backend calls are retried on throttling and transient errors.
+///
+/// Attempt `n` is followed by a delay of `base_delay * 2^(n-1)`, capped at `max_delay`.
+/// With jitter the delay is drawn uniformly from the upper half of that range, so
+/// clients throttled together do not come back together. Errors that are not
+/// transient, such as validation errors, are never retried.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+    pub metrics: Arc<dyn RetryMetrics>
+}
+
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("base_delay", &self.base_delay)
+            .field("max_delay", &self.max_delay)
+            .field("jitter", &self.jitter)
+            .finish()
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 8,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+            metrics: Arc::new(NoMetrics)
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// Send every request once.
+    pub fn no_retries() -> RetryPolicy {
+        RetryPolicy::default().max_attempts(1)
+    }
+
+    /// Attempts per request, including the first. Zero is treated as one.
+    pub fn max_attempts(self, max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            ..self
+        }
+    }
+
+    pub fn base_delay(self, base_delay: Duration) -> RetryPolicy {
+        RetryPolicy {
+            base_delay,
+            ..self
+        }
+    }
+
+    pub fn max_delay(self, max_delay: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_delay,
+            ..self
+        }
+    }
+
+    pub fn jitter(self, jitter: bool) -> RetryPolicy {
+        RetryPolicy {
+            jitter,
+            ..self
+        }
+    }
+
+    pub fn metrics(self, metrics: Arc<dyn RetryMetrics>) -> RetryPolicy {
+        RetryPolicy {
+            metrics,
+            ..self
+        }
+    }
+
+    /// Delay before the attempt following attempt number `attempt`, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.checked_pow(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        let delay = self.base_delay.checked_mul(factor).unwrap_or(self.max_delay).min(self.max_delay);
+        if self.jitter {
+            let half = delay / 2;
+            half + half.mul_f64(rand::thread_rng().gen::<f64>())
+        } else {
+            delay
+        }
+    }
+
+    /// Run `call` until it succeeds, fails with an error that is not transient, or runs out of attempts.
+    pub async fn run<T, F, Fut>(&self, operation: &str, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let err = match call().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err
+            };
+            if let DatastoreError::Throttled(_) = err {
+                self.metrics.on_throttle(operation);
+            }
+            if !err.is_transient() {
+                return Err(err);
+            }
+            if attempt >= self.max_attempts {
+                self.metrics.on_give_up(operation, attempt);
+                return Err(err);
+            }
+            let delay = self.delay(attempt);
+            warn!("{} failed on attempt {}, retrying in {:?}: {}", operation, attempt, delay, err);
+            self.metrics.on_retry(operation, attempt, delay);
+            tokio::time::delay_for(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct CountingMetrics {
+        counts: Mutex<(u32, u32, u32)>
+    }
+
+    impl RetryMetrics for CountingMetrics {
+        fn on_throttle(&self, _operation: &str) {
+            self.counts.lock().unwrap().0 += 1;
+        }
+
+        fn on_retry(&self, _operation: &str, _attempt: u32, _delay: Duration) {
+            self.counts.lock().unwrap().1 += 1;
+        }
+
+        fn on_give_up(&self, _operation: &str, _attempts: u32) {
+            self.counts.lock().unwrap().2 += 1;
+        }
+    }
+
+    fn fast_policy(metrics: Arc<CountingMetrics>) -> RetryPolicy {
+        RetryPolicy::new()
+            .max_attempts(3)
+            .base_delay(Duration::from_millis(1))
+            .metrics(metrics)
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let policy = RetryPolicy::new()
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_secs(1))
+            .jitter(false);
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+        assert_eq!(policy.delay(5), Duration::from_secs(1));
+        assert_eq!(policy.delay(64), Duration::from_secs(1));
+
+        let policy = policy.jitter(true);
+        for attempt in 1..6 {
+            let delay = policy.delay(attempt);
+            let full = policy.clone().jitter(false).delay(attempt);
+            assert!(delay >= full / 2 && delay <= full);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_throttled_calls_until_they_succeed() {
+        let metrics = Arc::new(CountingMetrics::default());
+        let mut calls = 0;
+        let result = fast_policy(metrics.clone()).run("Query", || {
+            calls += 1;
+            let failed = calls < 3;
+            async move {
+                if failed {
+                    Err(DatastoreError::Throttled(String::from("ProvisionedThroughputExceeded")))
+                } else {
+                    Ok(calls)
+                }
+            }
+        }).await;
+
+        assert_eq!(result, Ok(3));
+        assert_eq!(*metrics.counts.lock().unwrap(), (2, 2, 0));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let metrics = Arc::new(CountingMetrics::default());
+        let result: Result<()> = fast_policy(metrics.clone())
+            .run("PutItem", || async { Err(DatastoreError::Unavailable(String::from("connection reset"))) })
+            .await;
+
+        assert!(matches!(result, Err(DatastoreError::Unavailable(_))));
+        assert_eq!(*metrics.counts.lock().unwrap(), (0, 2, 1));
+    }
+
+    #[tokio::test]
+    async fn never_retries_validation_errors() {
+        let metrics = Arc::new(CountingMetrics::default());
+        let mut calls = 0;
+        let result: Result<()> = fast_policy(metrics.clone()).run("PutItem", || {
+            calls += 1;
+            async { Err(DatastoreError::Backend(String::from("ValidationException"))) }
+        }).await;
+
+        assert!(matches!(result, Err(DatastoreError::Backend(_))));
+        assert_eq!(calls, 1);
+        assert_eq!(*metrics.counts.lock().unwrap(), (0, 0, 0));
+    }
+}
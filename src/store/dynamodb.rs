@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use async_trait::async_trait;
+use futures::TryFutureExt;
+use rusoto_core::{Region, RusotoError};
+use rusoto_dynamodb::{
+    AttributeDefinition,
+    AttributeValue,
+    BatchWriteItemError,
+    BatchWriteItemInput,
+    CreateTableError,
+    CreateTableInput,
+    Delete,
+    DeleteItemError,
+    DeleteItemInput,
+    DynamoDb,
+    DynamoDbClient,
+    GetItemError,
+    GetItemInput,
+    GlobalSecondaryIndex,
+    KeySchemaElement,
+    Projection,
+    ProvisionedThroughput,
+    Put,
+    PutItemError,
+    PutItemInput,
+    PutRequest,
+    QueryError,
+    QueryInput,
+    TransactWriteItem,
+    TransactWriteItemsError,
+    TransactWriteItemsInput,
+    TimeToLiveSpecification,
+    UpdateTimeToLiveError,
+    UpdateTimeToLiveInput,
+    WriteRequest
+};
+
+use crate::error::Result;
+
+use log::{info, debug};
+
+use uuid::Uuid;
+
+use super::{GraphStore, PageKey, PageRequest, QueryOutput, WriteCondition, WriteOp, BATCH_WRITE_LIMIT, TTL_ATTRIBUTE, decode_items, validate_transaction};
+use crate::config::{BillingMode, GraphDbConfig};
+use crate::domain::Edge;
+use crate::error::DatastoreError;
+use crate::retry::RetryPolicy;
+
+pub struct DynamoDbStore {
+    pub client: DynamoDbClient,
+    pub table_name: String,
+    pub vertex_b_index: String,
+    pub billing_mode: BillingMode,
+    pub retry_policy: RetryPolicy
+}
+
+/// DynamoDB service errors that are worth sending again.
+trait RetryableError: std::error::Error + 'static {
+    /// Throttling: provisioned throughput or the account request limit was exceeded.
+    fn throttled(&self) -> bool;
+
+    /// A failure on the DynamoDB side.
+    fn server_error(&self) -> bool;
+}
+
+macro_rules! retryable_error {
+    ($error:ident) => {
+        impl RetryableError for $error {
+            fn throttled(&self) -> bool {
+                matches!(self, $error::ProvisionedThroughputExceeded(_) | $error::RequestLimitExceeded(_))
+            }
+
+            fn server_error(&self) -> bool {
+                matches!(self, $error::InternalServerError(_))
+            }
+        }
+    };
+}
+
+retryable_error!(GetItemError);
+retryable_error!(QueryError);
+retryable_error!(PutItemError);
+retryable_error!(DeleteItemError);
+retryable_error!(BatchWriteItemError);
+retryable_error!(TransactWriteItemsError);
+
+impl RetryableError for CreateTableError {
+    fn throttled(&self) -> bool {
+        false
+    }
+
+    fn server_error(&self) -> bool {
+        matches!(self, CreateTableError::InternalServerError(_))
+    }
+}
+
+impl RetryableError for UpdateTimeToLiveError {
+    fn throttled(&self) -> bool {
+        false
+    }
+
+    fn server_error(&self) -> bool {
+        matches!(self, UpdateTimeToLiveError::InternalServerError(_))
+    }
+}
+
+/// Map a rusoto error to `Throttled` or `Unavailable` when a retry may help, and to a non-transient error otherwise.
+fn classify<E: RetryableError>(err: RusotoError<E>) -> DatastoreError {
+    match &err {
+        RusotoError::Service(service) if service.throttled() => DatastoreError::Throttled(err.to_string()),
+        RusotoError::Service(service) if service.server_error() => DatastoreError::Unavailable(err.to_string()),
+        _ => DatastoreError::from(err)
+    }
+}
+
+fn string_value(val: &str) -> AttributeValue {
+    AttributeValue {
+        s: Some(String::from(val)),
+        ..Default::default()
+    }
+}
+
+fn edge_key(vertex_a: &str, edge: &str) -> HashMap<String, AttributeValue> {
+    [(String::from("vertex_a"), string_value(vertex_a)),
+    (String::from("edge"), string_value(edge))]
+    .iter().cloned().collect()
+}
+
+/// Condition expression and its values for a conditional put.
+fn condition_expression(condition: WriteCondition) -> (String, Option<HashMap<String, AttributeValue>>) {
+    match condition {
+        WriteCondition::NotExists => (String::from("attribute_not_exists(vertex_a)"), None),
+        WriteCondition::Version(version) => {
+            let mut values = HashMap::new();
+            values.insert(String::from(":expected_version"), AttributeValue {
+                n: Some(version.to_string()),
+                ..Default::default()
+            });
+            let expression = if version == 0 {
+                // Edges written without a version count as version 0, as in `WriteCondition::holds`
+                "attribute_exists(vertex_a) and (attribute_not_exists(version) or version = :expected_version)"
+            } else {
+                "version = :expected_version"
+            };
+            (String::from(expression), Some(values))
+        }
+    }
+}
+
+fn put_conflict(err: RusotoError<PutItemError>, edge: &Edge) -> DatastoreError {
+    match err {
+        RusotoError::Service(PutItemError::ConditionalCheckFailed(_)) |
+        RusotoError::Service(PutItemError::TransactionConflict(_)) => DatastoreError::Conflict(format!("{} {}", edge.vertex_a, edge.edge)),
+        err => classify(err)
+    }
+}
+
+/// A transaction cancelled by a failed condition or a concurrent transaction is a conflict.
+/// One cancelled only because an item was throttled can be retried as a whole.
+fn transaction_conflict(err: RusotoError<TransactWriteItemsError>) -> DatastoreError {
+    match err {
+        RusotoError::Service(TransactWriteItemsError::TransactionCanceled(reasons))
+            if reasons.contains("ConditionalCheckFailed") || reasons.contains("TransactionConflict") => DatastoreError::Conflict(reasons),
+        RusotoError::Service(TransactWriteItemsError::TransactionCanceled(reasons))
+            if reasons.contains("ThrottlingError") || reasons.contains("ProvisionedThroughputExceeded") => DatastoreError::Throttled(reasons),
+        err => classify(err)
+    }
+}
+
+fn page_key_to_item(key: &PageKey) -> HashMap<String, AttributeValue> {
+    let mut item: HashMap<String, AttributeValue> = HashMap::new();
+    item.insert(String::from("vertex_a"), string_value(&key.vertex_a));
+    item.insert(String::from("edge"), string_value(&key.edge));
+    if let Some(vertex_b) = &key.vertex_b {
+        item.insert(String::from("vertex_b"), string_value(vertex_b));
+    }
+    item
+}
+
+fn item_to_page_key(item: &HashMap<String, AttributeValue>) -> Option<PageKey> {
+    let key_attribute = |name: &str| item.get(name).and_then(|value| value.s.clone());
+    Some(PageKey {
+        vertex_a: key_attribute("vertex_a")?,
+        edge: key_attribute("edge")?,
+        vertex_b: key_attribute("vertex_b")
+    })
+}
+
+impl DynamoDbStore {
+    pub fn new(region: Region) -> DynamoDbStore {
+        DynamoDbStore::from_config(&GraphDbConfig::new().region(region))
+    }
+
+    pub fn from_config(config: &GraphDbConfig) -> DynamoDbStore {
+        info!("Create dynamodb client for table {} in {:?}", config.table_name, config.region);
+        DynamoDbStore {
+            client: DynamoDbClient::new(config.region.clone()),
+            table_name: config.table_name.clone(),
+            vertex_b_index: config.vertex_b_index.clone(),
+            billing_mode: config.billing_mode.clone(),
+            retry_policy: config.retry_policy.clone()
+        }
+    }
+
+    fn provisioned_throughput(&self) -> Option<ProvisionedThroughput> {
+        match self.billing_mode {
+            BillingMode::Provisioned { read_capacity_units, write_capacity_units } => Some(ProvisionedThroughput {
+                read_capacity_units,
+                write_capacity_units
+            }),
+            BillingMode::PayPerRequest => None
+        }
+    }
+
+    fn billing_mode_name(&self) -> &'static str {
+        match self.billing_mode {
+            BillingMode::Provisioned { .. } => "PROVISIONED",
+            BillingMode::PayPerRequest => "PAY_PER_REQUEST"
+        }
+    }
+
+    pub async fn create_table(&self) -> Result<()> {
+        let input = CreateTableInput{
+            table_name: self.table_name.clone(),
+            billing_mode: Some(String::from(self.billing_mode_name())),
+            key_schema: vec![
+                KeySchemaElement {
+                    attribute_name: "vertex_a".into(),
+                    key_type: "HASH".into(),
+                },
+                KeySchemaElement {
+                    attribute_name: "edge".into(),
+                    key_type: "RANGE".into(),
+                }
+            ],
+            attribute_definitions: vec![
+                AttributeDefinition {
+                    attribute_name: "vertex_a".into(),
+                    attribute_type: "S".into(),
+                },
+                AttributeDefinition {
+                    attribute_name: "vertex_b".into(),
+                    attribute_type: "S".into(),
+                },
+                AttributeDefinition {
+                    attribute_name: "edge".into(),
+                    attribute_type: "S".into(),
+                }
+            ],
+            global_secondary_indexes: Some(vec![
+                GlobalSecondaryIndex{
+                    index_name: self.vertex_b_index.clone(),
+                    key_schema: vec![
+                        KeySchemaElement {
+                            attribute_name: "vertex_b".into(),
+                            key_type: "HASH".into(),
+                        },
+                        KeySchemaElement {
+                            attribute_name: "edge".into(),
+                            key_type: "RANGE".into(),
+                        }
+                    ],
+                    projection: Projection {
+                        non_key_attributes: None,
+                        projection_type: Some("ALL".into())
+
+                    },
+                    provisioned_throughput: self.provisioned_throughput()
+                }
+            ]),
+            provisioned_throughput: self.provisioned_throughput(),
+            ..CreateTableInput::default()
+        };
+        let create_res = self.retry_policy.run("CreateTable", || self.client.create_table(input.clone()).map_err(classify)).await?;
+
+        match create_res.table_description {
+            Some(desc) => info!("Created table {:?}", desc),
+            None => info!("Created table, no table description")
+        };
+
+        Ok(())
+    }
+
+    /// Let DynamoDB delete expired edges, such as those of abandoned sessions. The table must be active.
+    pub async fn enable_ttl(&self) -> Result<()> {
+        let input = UpdateTimeToLiveInput {
+            table_name: self.table_name.clone(),
+            time_to_live_specification: TimeToLiveSpecification {
+                attribute_name: String::from(TTL_ATTRIBUTE),
+                enabled: true
+            }
+        };
+        self.retry_policy.run("UpdateTimeToLive", || self.client.update_time_to_live(input.clone()).map_err(classify)).await?;
+        info!("Enabled time to live on {} of table {}", TTL_ATTRIBUTE, self.table_name);
+        Ok(())
+    }
+
+    /// The raw item stored under the key, read with a strongly consistent read.
+    async fn get_item(&self, vertex_a: &str, edge: &str) -> Result<Option<HashMap<String, AttributeValue>>> {
+        let input = GetItemInput {
+            table_name: self.table_name.clone(),
+            key: edge_key(vertex_a, edge),
+            consistent_read: Some(true),
+            ..GetItemInput::default()
+        };
+        let res = self.retry_policy.run("GetItem", || self.client.get_item(input.clone()).map_err(classify)).await?;
+        Ok(res.item)
+    }
+
+    async fn query(&self, key_attribute: &str, vertex: &str, edge_prefix: Option<&str>, index_name: Option<String>, page: &PageRequest) -> Result<QueryOutput> {
+        let mut values: HashMap<String, AttributeValue> = HashMap::new();
+        values.insert(String::from(":vertex"), string_value(vertex));
+
+        let key_condition = match edge_prefix {
+            Some(prefix) => {
+                values.insert(String::from(":edge_prefix"), string_value(prefix));
+                format!("{} = :vertex and begins_with(edge, :edge_prefix)", key_attribute)
+            },
+            None => format!("{} = :vertex", key_attribute)
+        };
+
+        let input = QueryInput{
+            table_name: self.table_name.clone(),
+            consistent_read: Some(page.consistent_read && index_name.is_none()),
+            index_name,
+            key_condition_expression: Some(key_condition),
+            expression_attribute_values: Some(values),
+            limit: page.limit,
+            exclusive_start_key: page.start.as_ref().map(page_key_to_item),
+            .. QueryInput::default()
+        };
+        let res = self.retry_policy.run("Query", || self.client.query(input.clone()).map_err(classify)).await?;
+
+        Ok(QueryOutput {
+            next: res.last_evaluated_key.as_ref().and_then(item_to_page_key),
+            ..decode_items(res.items.unwrap_or_default())
+        })
+    }
+}
+
+#[async_trait]
+impl GraphStore for DynamoDbStore {
+    async fn put_edge(&self, edge: &Edge) -> Result<()> {
+        debug!("Store edge {} {} {:?}", edge.vertex_a, edge.vertex_b, edge.data);
+        let input = PutItemInput{
+            item: serde_dynamodb::to_hashmap(edge)?,
+            table_name: self.table_name.clone(),
+            ..PutItemInput::default()
+        };
+        let put_res = self.retry_policy.run("PutItem", || self.client.put_item(input.clone()).map_err(classify)).await?;
+        info!("ok! {:?}", put_res);
+        Ok(())
+    }
+
+    async fn put_edge_if(&self, edge: &Edge, condition: WriteCondition) -> Result<()> {
+        let (expression, values) = condition_expression(condition);
+        let input = PutItemInput{
+            item: serde_dynamodb::to_hashmap(edge)?,
+            table_name: self.table_name.clone(),
+            condition_expression: Some(expression),
+            expression_attribute_values: values,
+            ..PutItemInput::default()
+        };
+        // An attempt that failed with `Unavailable` may still have been applied, in which case
+        // the retry fails its own condition. Only the stored item can tell the two apart.
+        let ambiguous = AtomicBool::new(false);
+        let res = self.retry_policy.run("PutItem", || self.client.put_item(input.clone()).map_err(|err| {
+            let err = put_conflict(err, edge);
+            if let DatastoreError::Unavailable(_) = err {
+                ambiguous.store(true, Ordering::SeqCst);
+            }
+            err
+        })).await;
+        match res {
+            Err(DatastoreError::Conflict(_)) if ambiguous.load(Ordering::SeqCst) => {
+                if self.get_item(&edge.vertex_a, &edge.edge).await?.as_ref() == Some(&input.item) {
+                    Ok(())
+                } else {
+                    Err(DatastoreError::Conflict(format!("{} {}", edge.vertex_a, edge.edge)))
+                }
+            },
+            res => res.map(|_| ())
+        }
+    }
+
+    async fn query_edges_from(&self, vertex_a: &str, edge_prefix: Option<&str>, page: &PageRequest) -> Result<QueryOutput> {
+        self.query("vertex_a", vertex_a, edge_prefix, None, page).await
+    }
+
+    async fn query_edges_to(&self, vertex_b: &str, edge_prefix: Option<&str>, page: &PageRequest) -> Result<QueryOutput> {
+        self.query("vertex_b", vertex_b, edge_prefix, Some(self.vertex_b_index.clone()), page).await
+    }
+
+    async fn put_edges(&self, edges: &[Edge]) -> Result<Vec<Edge>> {
+        if edges.len() > BATCH_WRITE_LIMIT {
+            return Err(DatastoreError::Backend(format!("Batch of {} edges exceeds the limit of {}", edges.len(), BATCH_WRITE_LIMIT)));
+        }
+        let write_requests = edges.iter()
+            .map(|edge| Ok(WriteRequest {
+                put_request: Some(PutRequest { item: serde_dynamodb::to_hashmap(edge)? }),
+                delete_request: None
+            }))
+            .collect::<Result<Vec<WriteRequest>>>()?;
+
+        let mut request_items = HashMap::new();
+        request_items.insert(self.table_name.clone(), write_requests);
+        let input = BatchWriteItemInput {
+            request_items,
+            ..BatchWriteItemInput::default()
+        };
+        let res = self.retry_policy.run("BatchWriteItem", || self.client.batch_write_item(input.clone()).map_err(classify)).await?;
+
+        let unprocessed = res.unprocessed_items
+            .and_then(|mut tables| tables.remove(&self.table_name))
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|request| request.put_request)
+            .map(|put| serde_dynamodb::from_hashmap(put.item).map_err(DatastoreError::from))
+            .collect::<Result<Vec<Edge>>>()?;
+        Ok(unprocessed)
+    }
+
+    async fn transact_write(&self, ops: &[WriteOp]) -> Result<()> {
+        validate_transaction(ops)?;
+        let transact_items = ops.iter()
+            .map(|op| Ok(match op {
+                WriteOp::Put(edge) => TransactWriteItem {
+                    put: Some(Put {
+                        item: serde_dynamodb::to_hashmap(edge)?,
+                        table_name: self.table_name.clone(),
+                        ..Put::default()
+                    }),
+                    ..TransactWriteItem::default()
+                },
+                WriteOp::PutIf(edge, condition) => {
+                    let (expression, values) = condition_expression(*condition);
+                    TransactWriteItem {
+                        put: Some(Put {
+                            item: serde_dynamodb::to_hashmap(edge)?,
+                            table_name: self.table_name.clone(),
+                            condition_expression: Some(expression),
+                            expression_attribute_values: values,
+                            ..Put::default()
+                        }),
+                        ..TransactWriteItem::default()
+                    }
+                },
+                WriteOp::Delete { vertex_a, edge } => TransactWriteItem {
+                    delete: Some(Delete {
+                        key: edge_key(vertex_a, edge),
+                        table_name: self.table_name.clone(),
+                        ..Delete::default()
+                    }),
+                    ..TransactWriteItem::default()
+                }
+            }))
+            .collect::<Result<Vec<TransactWriteItem>>>()?;
+
+        // The same token on every attempt makes DynamoDB treat a retry of an applied transaction as a success
+        let input = TransactWriteItemsInput {
+            transact_items,
+            client_request_token: Some(Uuid::new_v4().to_hyphenated().to_string()),
+            ..TransactWriteItemsInput::default()
+        };
+        self.retry_policy.run("TransactWriteItems", || self.client.transact_write_items(input.clone()).map_err(transaction_conflict)).await?;
+        Ok(())
+    }
+
+    async fn delete_edge(&self, vertex_a: &str, edge: &str) -> Result<()> {
+        let input = DeleteItemInput{
+            table_name: self.table_name.clone(),
+            key: edge_key(vertex_a, edge),
+            ..DeleteItemInput::default()
+        };
+        self.retry_policy.run("DeleteItem", || self.client.delete_item(input.clone()).map_err(classify)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn version_zero_condition_matches_write_condition_holds() {
+        let (expression, values) = condition_expression(WriteCondition::Version(0));
+        assert_eq!(expression, "attribute_exists(vertex_a) and (attribute_not_exists(version) or version = :expected_version)");
+        assert_eq!(values.unwrap()[":expected_version"].n.as_deref(), Some("0"));
+
+        let (expression, values) = condition_expression(WriteCondition::Version(3));
+        assert_eq!(expression, "version = :expected_version");
+        assert_eq!(values.unwrap()[":expected_version"].n.as_deref(), Some("3"));
+    }
+}
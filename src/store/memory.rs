@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::error::{DatastoreError, Result};
+
+use super::{CorruptEdge, GraphStore, PageKey, PageRequest, QueryOutput, WriteCondition, WriteOp, validate_transaction};
+use crate::domain::Edge;
+
+/// Error to fail a call with, given the name of the `GraphStore` method called.
+type Fault = dyn Fn(&str) -> Option<DatastoreError> + Send + Sync;
+
+/// In-memory edge table for tests and local development.
+///
+/// Mirrors the DynamoDB table layout: items are keyed on (`vertex_a`, `edge`)
+/// and the `index-vertex_b_edges` index is keyed on (`vertex_b`, `edge`).
+/// Both are kept sorted on the range key so queries return edges in the same
+/// order as DynamoDB would.
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+    page_size: Option<usize>,
+    unprocessed_budget: Mutex<usize>,
+    fault: Option<Box<Fault>>,
+    corrupt_items: bool
+}
+
+#[derive(Default)]
+struct Tables {
+    edges: BTreeMap<(String, String), Edge>,
+    vertex_b_index: BTreeSet<(String, String, String)>
+}
+
+impl Tables {
+    fn insert(&mut self, edge: Edge) {
+        self.remove(&edge.vertex_a, &edge.edge);
+        self.vertex_b_index.insert((edge.vertex_b.clone(), edge.edge.clone(), edge.vertex_a.clone()));
+        self.edges.insert((edge.vertex_a.clone(), edge.edge.clone()), edge);
+    }
+
+    fn check(&self, edge: &Edge, condition: WriteCondition) -> Result<()> {
+        let stored = self.edges.get(&(edge.vertex_a.clone(), edge.edge.clone()));
+        if condition.holds(stored) {
+            Ok(())
+        } else {
+            Err(DatastoreError::Conflict(format!("{} {} does not meet {:?}", edge.vertex_a, edge.edge, condition)))
+        }
+    }
+
+    fn remove(&mut self, vertex_a: &str, edge: &str) {
+        if let Some(old) = self.edges.remove(&(String::from(vertex_a), String::from(edge))) {
+            self.vertex_b_index.remove(&(old.vertex_b, old.edge, old.vertex_a));
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// Stop every query page after `page_size` items, standing in for the 1 MB page limit of DynamoDB.
+    pub fn with_page_size(page_size: usize) -> MemoryStore {
+        MemoryStore {
+            page_size: Some(page_size),
+            ..MemoryStore::default()
+        }
+    }
+
+    /// Leave the next `count` edges offered to `put_edges` unprocessed, like a throttled `BatchWriteItem`.
+    pub fn leave_unprocessed(&self, count: usize) {
+        *self.unprocessed_budget.lock().unwrap() = count;
+    }
+
+    /// Fail every call `fault` returns an error for, given the name of the `GraphStore` method called.
+    pub fn with_fault<F: Fn(&str) -> Option<DatastoreError> + Send + Sync + 'static>(self, fault: F) -> MemoryStore {
+        MemoryStore {
+            fault: Some(Box::new(fault)),
+            ..self
+        }
+    }
+
+    /// Report one corrupt item next to every page of `query_edges_from`, like an item that no longer decodes.
+    pub fn with_corrupt_items(self) -> MemoryStore {
+        MemoryStore {
+            corrupt_items: true,
+            ..self
+        }
+    }
+
+    fn fault(&self, method: &str) -> Result<()> {
+        match self.fault.as_ref().and_then(|fault| fault(method)) {
+            Some(err) => Err(err),
+            None => Ok(())
+        }
+    }
+
+    fn page_limit(&self, page: &PageRequest) -> Option<usize> {
+        let limit = page.limit.map(|limit| limit.max(1) as usize);
+        match (limit, self.page_size) {
+            (Some(limit), Some(page_size)) => Some(limit.min(page_size)),
+            (limit, page_size) => limit.or(page_size)
+        }
+    }
+
+    /// Number of stored edges.
+    pub fn len(&self) -> usize {
+        self.tables.lock().unwrap().edges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All stored edges, ordered on (`vertex_a`, `edge`). The equivalent of a table scan.
+    pub fn scan(&self) -> Vec<Edge> {
+        self.tables.lock().unwrap().edges.values().cloned().collect()
+    }
+}
+
+/// Like DynamoDB, a page that stops at its limit carries the key of its last item even if nothing follows it.
+fn take_page<I: Iterator<Item = Edge>>(edges: I, limit: Option<usize>, vertex_b_index: bool) -> QueryOutput {
+    let edges: Vec<Edge> = match limit {
+        Some(limit) => edges.take(limit).collect(),
+        None => edges.collect()
+    };
+    let next = match (limit, edges.last()) {
+        (Some(limit), Some(last)) if edges.len() == limit => Some(PageKey {
+            vertex_a: last.vertex_a.clone(),
+            edge: last.edge.clone(),
+            vertex_b: if vertex_b_index { Some(last.vertex_b.clone()) } else { None }
+        }),
+        _ => None
+    };
+    QueryOutput {
+        edges,
+        next,
+        ..QueryOutput::default()
+    }
+}
+
+#[async_trait]
+impl GraphStore for MemoryStore {
+    async fn put_edge(&self, edge: &Edge) -> Result<()> {
+        self.fault("put_edge")?;
+        self.tables.lock().unwrap().insert(edge.clone());
+        Ok(())
+    }
+
+    async fn put_edge_if(&self, edge: &Edge, condition: WriteCondition) -> Result<()> {
+        self.fault("put_edge_if")?;
+        let mut tables = self.tables.lock().unwrap();
+        tables.check(edge, condition)?;
+        tables.insert(edge.clone());
+        Ok(())
+    }
+
+    async fn query_edges_from(&self, vertex_a: &str, edge_prefix: Option<&str>, page: &PageRequest) -> Result<QueryOutput> {
+        self.fault("query_edges_from")?;
+        let prefix = edge_prefix.unwrap_or("");
+        let start = match &page.start {
+            Some(key) => Bound::Excluded((String::from(vertex_a), key.edge.clone())),
+            None => Bound::Included((String::from(vertex_a), String::from(prefix)))
+        };
+        let tables = self.tables.lock().unwrap();
+        let edges = tables.edges
+            .range((start, Bound::Unbounded))
+            .take_while(|((a, edge), _)| a == vertex_a && edge.starts_with(prefix))
+            .map(|(_, edge)| edge.clone());
+        let mut output = take_page(edges, self.page_limit(page), false);
+        if self.corrupt_items {
+            output.corrupt.push(CorruptEdge {
+                vertex_a: Some(String::from(vertex_a)),
+                edge: None,
+                error: DatastoreError::Serialization(String::from("missing field `vertex_b`"))
+            });
+        }
+        Ok(output)
+    }
+
+    async fn query_edges_to(&self, vertex_b: &str, edge_prefix: Option<&str>, page: &PageRequest) -> Result<QueryOutput> {
+        self.fault("query_edges_to")?;
+        let prefix = edge_prefix.unwrap_or("");
+        let start = match &page.start {
+            Some(key) => Bound::Excluded((String::from(vertex_b), key.edge.clone(), key.vertex_a.clone())),
+            None => Bound::Included((String::from(vertex_b), String::from(prefix), String::new()))
+        };
+        let tables = self.tables.lock().unwrap();
+        let edges = tables.vertex_b_index
+            .range((start, Bound::Unbounded))
+            .take_while(|(b, edge, _)| b == vertex_b && edge.starts_with(prefix))
+            .filter_map(|(_, edge, a)| tables.edges.get(&(a.clone(), edge.clone())).cloned());
+        Ok(take_page(edges, self.page_limit(page), true))
+    }
+
+    async fn put_edges(&self, edges: &[Edge]) -> Result<Vec<Edge>> {
+        self.fault("put_edges")?;
+        let mut budget = self.unprocessed_budget.lock().unwrap();
+        let skip = (*budget).min(edges.len());
+        *budget -= skip;
+
+        let (unprocessed, processed) = edges.split_at(skip);
+        let mut tables = self.tables.lock().unwrap();
+        for edge in processed {
+            tables.insert(edge.clone());
+        }
+        Ok(unprocessed.to_vec())
+    }
+
+    async fn transact_write(&self, ops: &[WriteOp]) -> Result<()> {
+        self.fault("transact_write")?;
+        validate_transaction(ops)?;
+        let mut tables = self.tables.lock().unwrap();
+        for op in ops {
+            if let WriteOp::PutIf(edge, condition) = op {
+                tables.check(edge, *condition)?;
+            }
+        }
+        for op in ops {
+            match op {
+                WriteOp::Put(edge) | WriteOp::PutIf(edge, _) => tables.insert(edge.clone()),
+                WriteOp::Delete { vertex_a, edge } => tables.remove(vertex_a, edge)
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_edge(&self, vertex_a: &str, edge: &str) -> Result<()> {
+        self.fault("delete_edge")?;
+        self.tables.lock().unwrap().remove(vertex_a, edge);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::domain::*;
+    use crate::new_edge;
+
+    fn user(id: &str) -> Vertex {
+        Vertex::User(String::from(id))
+    }
+
+    #[tokio::test]
+    async fn put_replaces_edge_with_same_key() -> Result<()> {
+        let store = MemoryStore::new();
+        let pno = Vertex::PersonalNumber(String::from("191212121212"));
+        store.put_edge(&new_edge(&user("1"), &EdgeType::UserPersonalNumber, &pno, None)).await?;
+        store.put_edge(&new_edge(&user("1"), &EdgeType::UserPersonalNumber, &pno, Some(VertexData::String(String::from("x"))))).await?;
+
+        assert_eq!(store.len(), 1);
+        let edges = store.query_edges_to(&pno.to_string(), None, &PageRequest::default()).await?.edges;
+        assert_eq!(edges.len(), 1);
+        assert!(edges[0].data.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_from_matches_prefix_only() -> Result<()> {
+        let store = MemoryStore::new();
+        let doc = Vertex::Document(String::from("d"));
+        store.put_edge(&new_edge(&user("1"), &EdgeType::UserSelf, &user("1"), None)).await?;
+        store.put_edge(&new_edge(&user("1"), &EdgeType::DocumentOwner, &doc, None)).await?;
+        store.put_edge(&new_edge(&user("10"), &EdgeType::UserSelf, &user("10"), None)).await?;
+        store.put_edge(&new_edge(&user("2"), &EdgeType::UserSelf, &user("2"), None)).await?;
+
+        let usr = store.query_edges_from(&user("1").to_string(), Some("usr_"), &PageRequest::default()).await?.edges;
+        assert_eq!(usr.len(), 1);
+        assert_eq!(usr[0].vertex_b, user("1").to_string());
+
+        let all = store.query_edges_from(&user("1").to_string(), None, &PageRequest::default()).await?.edges;
+        assert_eq!(all.iter().map(|e| e.edge.as_str()).collect::<Vec<&str>>(),
+            vec!["doc_acl_owner|User-1|Document-d", "usr_self|User-1|User-1"]);
+
+        assert!(store.query_edges_from(&user("1").to_string(), Some("session_"), &PageRequest::default()).await?.edges.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_to_follows_vertex_b_index() -> Result<()> {
+        let store = MemoryStore::new();
+        let doc = Vertex::Document(String::from("d"));
+        store.put_edge(&new_edge(&user("1"), &EdgeType::DocumentOwner, &doc, None)).await?;
+        store.put_edge(&new_edge(&user("2"), &EdgeType::DocumentReader, &doc, None)).await?;
+        store.put_edge(&new_edge(&doc, &EdgeType::DocumentSelf, &doc, None)).await?;
+
+        assert_eq!(store.query_edges_to(&doc.to_string(), None, &PageRequest::default()).await?.edges.len(), 3);
+        let owners = store.query_edges_to(&doc.to_string(), Some("doc_acl_owner"), &PageRequest::default()).await?.edges;
+        assert_eq!(owners.len(), 1);
+        assert_eq!(owners[0].vertex_a, user("1").to_string());
+
+        store.delete_edge(&owners[0].vertex_a, &owners[0].edge).await?;
+        assert!(store.query_edges_to(&doc.to_string(), Some("doc_acl_owner"), &PageRequest::default()).await?.edges.is_empty());
+        assert_eq!(store.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn conditional_puts_check_stored_edge() -> Result<()> {
+        let store = MemoryStore::new();
+        let edge = Edge { version: Some(1), ..new_edge(&user("1"), &EdgeType::UserSelf, &user("1"), None) };
+        store.put_edge_if(&edge, WriteCondition::NotExists).await?;
+        assert!(matches!(store.put_edge_if(&edge, WriteCondition::NotExists).await, Err(DatastoreError::Conflict(_))));
+
+        let updated = Edge { version: Some(2), ..edge.clone() };
+        assert!(matches!(store.put_edge_if(&updated, WriteCondition::Version(0)).await, Err(DatastoreError::Conflict(_))));
+        store.put_edge_if(&updated, WriteCondition::Version(1)).await?;
+        assert_eq!(store.scan()[0].version, Some(2));
+
+        let unversioned = new_edge(&user("2"), &EdgeType::UserSelf, &user("2"), None);
+        assert!(matches!(store.put_edge_if(&unversioned, WriteCondition::Version(0)).await, Err(DatastoreError::Conflict(_))));
+        store.put_edge(&unversioned).await?;
+        store.put_edge_if(&Edge { version: Some(1), ..unversioned }, WriteCondition::Version(0)).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pages_resume_after_last_key() -> Result<()> {
+        let store = MemoryStore::with_page_size(2);
+        let doc = Vertex::Document(String::from("d"));
+        for id in &["1", "2", "3", "4", "5"] {
+            store.put_edge(&new_edge(&user(id), &EdgeType::DocumentReader, &doc, None)).await?;
+            store.put_edge(&new_edge(&doc, &EdgeType::DocumentSignRequest, &user(id), None)).await?;
+        }
+
+        for vertex_b_index in &[false, true] {
+            let mut page = PageRequest::default();
+            let mut seen = Vec::new();
+            loop {
+                let output = if *vertex_b_index {
+                    store.query_edges_to(&doc.to_string(), Some("doc_acl_"), &page).await?
+                } else {
+                    store.query_edges_from(&doc.to_string(), Some("doc_signreq"), &page).await?
+                };
+                assert!(output.edges.len() <= 2);
+                seen.extend(output.edges.into_iter().map(|edge| edge.edge));
+                match output.next {
+                    Some(next) => page = PageRequest::after(Some(next)),
+                    None => break
+                }
+            }
+            assert_eq!(seen.len(), 5);
+        }
+
+        let output = store.query_edges_from(&doc.to_string(), None, &PageRequest { limit: Some(1), ..PageRequest::default() }).await?;
+        assert_eq!(output.edges.len(), 1);
+        assert!(output.next.is_some());
+        Ok(())
+    }
+}
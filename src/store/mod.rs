@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use rusoto_dynamodb::AttributeValue;
+
+use crate::error::{DatastoreError, Result};
+
+use crate::domain::Edge;
+
+pub mod dynamodb;
+pub mod memory;
+
+pub use dynamodb::DynamoDbStore;
+pub use memory::MemoryStore;
+
+/// An item that was read from the table but could not be decoded into an `Edge`.
+#[derive(Clone, Debug)]
+pub struct CorruptEdge {
+    pub vertex_a: Option<String>,
+    pub edge: Option<String>,
+    pub error: DatastoreError
+}
+
+impl std::fmt::Display for CorruptEdge {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "CorruptEdge{{")?;
+        match &self.vertex_a {
+            Some(s) => write!(f, ", vertex_a: Some(\"{}\")", s)?,
+            None => write!(f, ", vertex_a: None")?
+        };
+        match &self.edge {
+            Some(s) => write!(f, ", edge: Some(\"{}\")", s)?,
+            None => write!(f, ", edge: None")?
+        };
+        write!(f, ", error: \"{}\"", self.error)?;
+        write!(f, "}}")
+    }
+}
+
+/// Key of the last item a query evaluated, the DynamoDB `LastEvaluatedKey`.
+///
+/// `vertex_b` is only set for queries on the `vertex_b` index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageKey {
+    pub vertex_a: String,
+    pub edge: String,
+    pub vertex_b: Option<String>
+}
+
+fn hex_encode(val: &str) -> String {
+    val.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(val: &str) -> Option<String> {
+    let bytes = val.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => Some(((*high as char).to_digit(16)? * 16 + (*low as char).to_digit(16)?) as u8),
+            _ => None
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+impl PageKey {
+    /// Opaque continuation token for handing out to clients.
+    pub fn to_token(&self) -> String {
+        let mut parts = vec![hex_encode(&self.vertex_a), hex_encode(&self.edge)];
+        if let Some(vertex_b) = &self.vertex_b {
+            parts.push(hex_encode(vertex_b));
+        }
+        parts.join(".")
+    }
+
+    pub fn from_token(token: &str) -> Result<PageKey> {
+        let invalid = || DatastoreError::Serialization(format!("Invalid continuation token {}", token));
+        let parts = token.split('.')
+            .map(hex_decode)
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(invalid)?;
+        match parts.as_slice() {
+            [vertex_a, edge] => Ok(PageKey { vertex_a: vertex_a.clone(), edge: edge.clone(), vertex_b: None }),
+            [vertex_a, edge, vertex_b] => Ok(PageKey { vertex_a: vertex_a.clone(), edge: edge.clone(), vertex_b: Some(vertex_b.clone()) }),
+            _ => Err(invalid())
+        }
+    }
+}
+
+/// Where a query starts and how many items it may evaluate.
+#[derive(Clone, Debug, Default)]
+pub struct PageRequest {
+    pub limit: Option<i64>,
+    pub start: Option<PageKey>,
+    /// Read with a strongly consistent read. Ignored on the `vertex_b` index, which only supports eventually consistent reads.
+    pub consistent_read: bool
+}
+
+impl PageRequest {
+    pub fn after(start: Option<PageKey>) -> PageRequest {
+        PageRequest {
+            start,
+            ..PageRequest::default()
+        }
+    }
+
+    /// The first page, read with a strongly consistent read.
+    pub fn consistent() -> PageRequest {
+        PageRequest {
+            consistent_read: true,
+            ..PageRequest::default()
+        }
+    }
+}
+
+/// One page of a query: the edges that decoded, the items that did not and where the next page starts.
+#[derive(Clone, Debug, Default)]
+pub struct QueryOutput {
+    pub edges: Vec<Edge>,
+    pub corrupt: Vec<CorruptEdge>,
+    pub next: Option<PageKey>
+}
+
+/// Decode raw DynamoDB items, keeping items that fail to decode aside instead of failing the whole query.
+pub fn decode_items(items: Vec<HashMap<String, AttributeValue>>) -> QueryOutput {
+    let mut output = QueryOutput::default();
+    for item in items {
+        let key_attribute = |name: &str| item.get(name).and_then(|value| value.s.clone());
+        let vertex_a = key_attribute("vertex_a");
+        let edge = key_attribute("edge");
+        match serde_dynamodb::from_hashmap::<Edge, _>(item) {
+            Ok(decoded) => output.edges.push(decoded),
+            Err(err) => output.corrupt.push(CorruptEdge {
+                vertex_a,
+                edge,
+                error: DatastoreError::from(err)
+            })
+        }
+    }
+    output
+}
+
+/// Most items a single batch write may carry.
+pub const BATCH_WRITE_LIMIT: usize = 25;
+
+/// Attribute DynamoDB's time to live reads the expiry of an edge from, `Edge::expires_at`.
+pub const TTL_ATTRIBUTE: &str = "expires_at";
+
+/// Most operations a single transaction may carry.
+pub const TRANSACT_WRITE_LIMIT: usize = 25;
+
+/// Condition the stored edge must meet for a write to go through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteCondition {
+    /// No edge with the same `vertex_a` and `edge` is stored.
+    NotExists,
+    /// The stored edge has this version. Edges written without a version count as version 0.
+    Version(u64)
+}
+
+impl WriteCondition {
+    /// Whether `stored`, the edge currently at the written key, meets the condition.
+    pub fn holds(&self, stored: Option<&Edge>) -> bool {
+        match (self, stored) {
+            (WriteCondition::NotExists, stored) => stored.is_none(),
+            (WriteCondition::Version(_), None) => false,
+            (WriteCondition::Version(version), Some(edge)) => edge.version.unwrap_or(0) == *version
+        }
+    }
+}
+
+/// One operation of an all-or-nothing write.
+#[derive(Clone, Debug)]
+pub enum WriteOp {
+    Put(Edge),
+    PutIf(Edge, WriteCondition),
+    Delete {
+        vertex_a: String,
+        edge: String
+    }
+}
+
+impl WriteOp {
+    pub fn delete(edge: &Edge) -> WriteOp {
+        WriteOp::Delete {
+            vertex_a: edge.vertex_a.clone(),
+            edge: edge.edge.clone()
+        }
+    }
+
+    /// The (`vertex_a`, `edge`) key the operation writes.
+    pub fn key(&self) -> (&str, &str) {
+        match self {
+            WriteOp::Put(edge) | WriteOp::PutIf(edge, _) => (&edge.vertex_a, &edge.edge),
+            WriteOp::Delete { vertex_a, edge } => (vertex_a, edge)
+        }
+    }
+}
+
+/// Reject transactions the way DynamoDB does: too many operations, or more than one operation on an item.
+pub fn validate_transaction(ops: &[WriteOp]) -> Result<()> {
+    if ops.len() > TRANSACT_WRITE_LIMIT {
+        return Err(DatastoreError::Backend(format!("Transaction of {} operations exceeds the limit of {}", ops.len(), TRANSACT_WRITE_LIMIT)));
+    }
+    let mut keys = std::collections::HashSet::new();
+    for op in ops {
+        if !keys.insert(op.key()) {
+            let (vertex_a, edge) = op.key();
+            return Err(DatastoreError::Backend(format!("Transaction has more than one operation on {} {}", vertex_a, edge)));
+        }
+    }
+    Ok(())
+}
+
+/// Storage backend for the edge table.
+///
+/// Edges are keyed on `vertex_a` (hash) and `edge` (range). A secondary index
+/// keyed on `vertex_b` and `edge` makes it possible to walk edges backwards.
+#[async_trait]
+pub trait GraphStore: Send + Sync {
+    /// Write an edge, replacing any edge with the same `vertex_a` and `edge`.
+    async fn put_edge(&self, edge: &Edge) -> Result<()>;
+
+    /// Write an edge only if the stored edge meets `condition`, failing with `DatastoreError::Conflict` otherwise.
+    async fn put_edge_if(&self, edge: &Edge, condition: WriteCondition) -> Result<()>;
+
+    /// One page of edges where `vertex_a` matches, optionally restricted to edges beginning with `edge_prefix`.
+    async fn query_edges_from(&self, vertex_a: &str, edge_prefix: Option<&str>, page: &PageRequest) -> Result<QueryOutput>;
+
+    /// One page of edges where `vertex_b` matches, optionally restricted to edges beginning with `edge_prefix`.
+    async fn query_edges_to(&self, vertex_b: &str, edge_prefix: Option<&str>, page: &PageRequest) -> Result<QueryOutput>;
+
+    /// Write up to `BATCH_WRITE_LIMIT` edges in one request and return the edges the backend left unprocessed.
+    async fn put_edges(&self, edges: &[Edge]) -> Result<Vec<Edge>> {
+        for edge in edges {
+            self.put_edge(edge).await?;
+        }
+        Ok(Vec::new())
+    }
+
+    /// Apply all operations or none of them. A failed `WriteOp::PutIf` fails the whole transaction with `DatastoreError::Conflict`.
+    async fn transact_write(&self, ops: &[WriteOp]) -> Result<()>;
+
+    /// Remove the edge with the given key. Removing a missing edge is not an error.
+    async fn delete_edge(&self, vertex_a: &str, edge: &str) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn string_value(val: &str) -> AttributeValue {
+        AttributeValue {
+            s: Some(String::from(val)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decode_items_sets_corrupt_items_aside() {
+        let good: HashMap<String, AttributeValue> = [
+            (String::from("vertex_a"), string_value("User-1")),
+            (String::from("vertex_b"), string_value("User-1")),
+            (String::from("edge"), string_value("usr_self|User-1|User-1"))
+        ].iter().cloned().collect();
+        let mut bad = good.clone();
+        bad.insert(String::from("edge"), string_value("usr_email|User-1|Email-x"));
+        bad.remove("vertex_b");
+
+        let output = decode_items(vec![good, bad]);
+        assert_eq!(output.edges.len(), 1);
+        assert_eq!(output.corrupt.len(), 1);
+        assert_eq!(output.corrupt[0].vertex_a.as_deref(), Some("User-1"));
+        assert_eq!(output.corrupt[0].edge.as_deref(), Some("usr_email|User-1|Email-x"));
+        assert!(matches!(output.corrupt[0].error, DatastoreError::Serialization(_)));
+    }
+
+    #[test]
+    fn version_zero_matches_unversioned_and_zero_versioned_edges() {
+        let edge = Edge {
+            vertex_a: String::from("User-1"),
+            vertex_b: String::from("User-1"),
+            edge: String::from("usr_self|User-1|User-1"),
+            data: None,
+            version: None,
+            expires_at: None
+        };
+        assert!(WriteCondition::Version(0).holds(Some(&edge)));
+        assert!(WriteCondition::Version(0).holds(Some(&Edge { version: Some(0), ..edge.clone() })));
+        assert!(!WriteCondition::Version(0).holds(Some(&Edge { version: Some(1), ..edge.clone() })));
+        assert!(!WriteCondition::Version(0).holds(None));
+    }
+
+    #[test]
+    fn page_key_token_round_trips() -> Result<()> {
+        let key = PageKey {
+            vertex_a: String::from("User-1"),
+            edge: String::from("doc_acl_owner|User-1|Document-ä.1"),
+            vertex_b: None
+        };
+        assert_eq!(PageKey::from_token(&key.to_token())?, key);
+
+        let key = PageKey { vertex_b: Some(String::from("Document-ä.1")), ..key };
+        assert_eq!(PageKey::from_token(&key.to_token())?, key);
+
+        assert!(PageKey::from_token("xyz").is_err());
+        assert!(PageKey::from_token("00").is_err());
+        assert!(PageKey::from_token("557.00").is_err());
+        Ok(())
+    }
+}